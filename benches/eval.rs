@@ -0,0 +1,104 @@
+//! Compares evaluating a tree pixel by pixel with `Operation::eval` against
+//! evaluating it row by row with `CompiledExpr::eval_batch`, for generated
+//! trees and for trees of a single operation over `x` and `y`.
+//!
+//! Run with `cargo bench`.
+
+use random_art::compiled::CompiledExpr;
+use random_art::grammar::{
+    ArtGrammar, OperationKind, OperationWeights, PerrigSongGrammar, RandomArtGrammar,
+};
+use random_art::operations::Operation;
+use random_art::renderer::RenderSpec;
+use random_art::seed::Seed;
+use random_art::vec3::Vec3;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SIZE: usize = 256;
+const RUNS: u32 = 5;
+
+/// Fastest of `RUNS` runs of `f`.
+fn time(mut f: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn points(spec: &RenderSpec, y: usize) -> (Vec<f64>, Vec<f64>) {
+    (0..SIZE)
+        .map(|x| spec.pixel_to_point(x, y, SIZE, SIZE))
+        .unzip()
+}
+
+fn bench(name: &str, root: &Operation) {
+    let spec = RenderSpec::default();
+    let rows: Vec<_> = (0..SIZE).map(|y| points(&spec, y)).collect();
+
+    let per_pixel = time(|| {
+        for (xs, ys) in &rows {
+            for (&x, &y) in xs.iter().zip(ys) {
+                black_box(root.eval(x, y, 0.5));
+            }
+        }
+    });
+
+    let compiled = CompiledExpr::compile(root);
+    let mut out = vec![Vec3::new(0.0, 0.0, 0.0); SIZE];
+    let per_row = time(|| {
+        for (xs, ys) in &rows {
+            compiled.eval_batch(xs, ys, 0.5, &mut out);
+            black_box(&out);
+        }
+    });
+
+    println!(
+        "{:<18} {:>5} instructions  eval {:>9.2?}  eval_batch {:>9.2?}  speedup {:.2}x",
+        name,
+        compiled.instructions().len(),
+        per_pixel,
+        per_row,
+        per_pixel.as_secs_f64() / per_row.as_secs_f64()
+    );
+}
+
+fn main() {
+    for depth in [4, 6, 8] {
+        let seed = Seed::from_string("bench");
+        bench(
+            &format!("random_art d{}", depth),
+            &RandomArtGrammar::from_seed(&seed).generate_tree(depth),
+        );
+        bench(
+            &format!("perrig_song d{}", depth),
+            &PerrigSongGrammar::from_seed(&seed).generate_tree(depth),
+        );
+        bench(
+            &format!("all ops d{}", depth),
+            &RandomArtGrammar::from_seed(&seed)
+                .with_weights(OperationWeights::uniform())
+                .generate_tree(depth),
+        );
+    }
+    println!();
+    for kind in OperationKind::ALL {
+        if kind.arity() == 0 {
+            continue;
+        }
+        let mut weights = OperationWeights::zero();
+        for leaf in [kind, OperationKind::VarX, OperationKind::VarY] {
+            weights.set(leaf, 1.0);
+        }
+        bench(
+            kind.name(),
+            &RandomArtGrammar::from_seed(&Seed::from_string("bench"))
+                .with_weights(weights)
+                .generate_tree(3),
+        );
+    }
+}
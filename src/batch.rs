@@ -0,0 +1,221 @@
+//! Output paths and manifests for rendering many seeds at once.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Csv,
+    Json,
+}
+
+impl ManifestFormat {
+    /// Picks the format from the extension: `.json` is JSON, anything else
+    /// is CSV.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("json") => ManifestFormat::Json,
+            _ => ManifestFormat::Csv,
+        }
+    }
+}
+
+/// One rendered image of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub seed: String,
+    pub depth: usize,
+    pub grammar: String,
+    pub output: String,
+}
+
+/// Fills in the `{seed}`, `{depth}`, `{grammar}` and `{index}` placeholders
+/// of an output file name template such as `{seed}_{depth}.png`. Characters
+/// of the seed and grammar that are unsafe in file names become `_`.
+pub fn expand_template(
+    template: &str,
+    seed: &str,
+    depth: usize,
+    grammar: &str,
+    index: usize,
+) -> String {
+    template
+        .replace("{seed}", &file_name_safe(seed))
+        .replace("{depth}", &depth.to_string())
+        .replace("{grammar}", &file_name_safe(grammar))
+        .replace("{index}", &index.to_string())
+}
+
+fn file_name_safe(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The first two indices of `outputs` naming the same file, if any. Seeds
+/// that only differ in unsafe characters, duplicate seeds and templates
+/// without `{seed}` or `{index}` all lead to such collisions.
+pub fn find_duplicate_output(outputs: &[String]) -> Option<(usize, usize)> {
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    for (index, output) in outputs.iter().enumerate() {
+        // Ignores `.` components and repeated separators
+        let path: PathBuf = Path::new(output).components().collect();
+        if let Some(&first) = seen.get(&path) {
+            return Some((first, index));
+        }
+        seen.insert(path, index);
+    }
+    None
+}
+
+/// Writes the entries as CSV with a `seed,depth,grammar,output` header, or
+/// as a JSON array of objects with the same fields.
+pub fn write_manifest(
+    path: &str,
+    format: ManifestFormat,
+    entries: &[ManifestEntry],
+) -> io::Result<()> {
+    let mut manifest = String::new();
+    match format {
+        ManifestFormat::Csv => {
+            manifest.push_str("seed,depth,grammar,output\n");
+            for entry in entries {
+                manifest.push_str(&format!(
+                    "{},{},{},{}\n",
+                    csv_field(&entry.seed),
+                    entry.depth,
+                    csv_field(&entry.grammar),
+                    csv_field(&entry.output)
+                ));
+            }
+        }
+        ManifestFormat::Json => {
+            let objects: Vec<String> = entries
+                .iter()
+                .map(|entry| {
+                    format!(
+                        "  {{\"seed\": {}, \"depth\": {}, \"grammar\": {}, \"output\": {}}}",
+                        json_string(&entry.seed),
+                        entry.depth,
+                        json_string(&entry.grammar),
+                        json_string(&entry.output)
+                    )
+                })
+                .collect();
+            if objects.is_empty() {
+                manifest.push_str("[]\n");
+            } else {
+                manifest.push_str(&format!("[\n{}\n]\n", objects.join(",\n")));
+            }
+        }
+    }
+    fs::write(path, manifest)
+}
+
+/// Quotes fields containing separators, quotes or line breaks (RFC 4180).
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Encodes a JSON string. Written by hand rather than with serde_json, which
+/// is only available with the optional `serde` feature.
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_templates_with_safe_file_names() {
+        assert_eq!(
+            expand_template(
+                "out/{grammar}/{seed}_{depth}_{index}.png",
+                "a/b c",
+                5,
+                "x.toml",
+                3
+            ),
+            "out/x.toml/a_b_c_5_3.png"
+        );
+    }
+
+    #[test]
+    fn finds_colliding_outputs() {
+        let outputs = |seeds: &[&str], template: &str| -> Vec<String> {
+            seeds
+                .iter()
+                .enumerate()
+                .map(|(i, seed)| expand_template(template, seed, 5, "perrig_song", i))
+                .collect()
+        };
+        let template = "generated/{seed}_{depth}.png";
+        assert_eq!(
+            find_duplicate_output(&outputs(&["1", "a/b", "2", "a_b"], template)),
+            Some((1, 3))
+        );
+        assert_eq!(
+            find_duplicate_output(&outputs(&["x", "y", "x"], template)),
+            Some((0, 2))
+        );
+        assert_eq!(
+            find_duplicate_output(&outputs(&["x", "y"], "generated/{depth}.png")),
+            Some((0, 1))
+        );
+        assert_eq!(
+            find_duplicate_output(&outputs(&["x", "x"], "generated/{index}.png")),
+            None
+        );
+        assert_eq!(
+            find_duplicate_output(&["a/./b.png".to_string(), "a//b.png".to_string()]),
+            Some((0, 1))
+        );
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_strings_match_serde_json() {
+        for text in [
+            "plain",
+            "quote \" and \\",
+            "tab\tline\nfeed\r",
+            "\u{1}\u{1f}",
+            "é ✓",
+        ] {
+            let decoded: String = serde_json::from_str(&json_string(text)).unwrap();
+            assert_eq!(decoded, text);
+        }
+    }
+}
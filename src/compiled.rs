@@ -0,0 +1,630 @@
+use crate::images::ImageSet;
+use crate::noise;
+use crate::operations::Operation;
+use crate::vec3::Vec3;
+
+/// A single step of a [`CompiledExpr`].
+///
+/// Instructions are in postfix order: each one pops its operands from the
+/// stack and pushes its result, so children always come before their parent.
+/// Coordinate transforms are the exception: they bracket their child between
+/// a transform instruction and `EndTransform`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    Constant(f64),
+    VarX,
+    VarY,
+    VarT,
+    Circle(f64, f64),
+    Sum,
+    Product,
+    Mod,
+    Sine,
+    Inverse,
+    PerChannelMask(f64),
+    BinaryMask(f64),
+    SmoothMix,
+    Well,
+    Tent,
+    RGB,
+    Cos,
+    Tan,
+    Atan2,
+    Exp,
+    Log,
+    Pow,
+    Abs,
+    Sqrt,
+    Min,
+    Max,
+    Clamp(f64, f64),
+    Perlin(u32),
+    Simplex(u32),
+    ValueNoise(u32),
+    Worley(u32),
+    Fbm(u32, u32),
+    Mandelbrot,
+    Julia(f64, f64),
+    /// Transforms the coordinates until the matching `EndTransform`.
+    Rotate(f64),
+    Scale(f64, f64),
+    Translate(f64, f64),
+    Polar,
+    Mirror,
+    Kaleidoscope(u32),
+    /// Pops dx and dy and offsets the coordinates by their channel means.
+    Warp,
+    /// Restores the coordinates from before the last transform.
+    EndTransform,
+    Image(u32),
+}
+
+/// An [`Operation`] lowered to a flat instruction array.
+///
+/// Evaluation runs a stack machine over a whole batch of pixels at once: every
+/// stack slot holds one array per color channel, so each instruction is a
+/// tight loop over the batch instead of a pointer-chasing walk of the tree
+/// per pixel. Results are bit-identical to [`Operation::eval`], because every
+/// instruction performs the same floating point operations in the same order.
+#[derive(Debug, Clone)]
+pub struct CompiledExpr {
+    code: Vec<Instruction>,
+    max_stack: usize,
+}
+
+/// Stack slot holding one value per pixel for each color channel.
+type Lanes = [Vec<f64>; 3];
+
+impl CompiledExpr {
+    pub fn compile(root: &Operation) -> Self {
+        let mut code = Vec::new();
+        let max_stack = Self::lower(root, &mut code, 0);
+        CompiledExpr { code, max_stack }
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.code
+    }
+
+    /// Appends the instructions for `op` and returns the highest stack size
+    /// reached, given `depth` values already on the stack.
+    fn lower(op: &Operation, code: &mut Vec<Instruction>, depth: usize) -> usize {
+        let mut max_depth = depth + 1;
+        let mut children = |children: &[&Operation], code: &mut Vec<Instruction>| {
+            for (i, child) in children.iter().enumerate() {
+                max_depth = max_depth.max(Self::lower(child, code, depth + i));
+            }
+        };
+        let instruction = match op {
+            Operation::Sum(a, b) => {
+                children(&[a, b], code);
+                Instruction::Sum
+            }
+            Operation::Product(a, b) => {
+                children(&[a, b], code);
+                Instruction::Product
+            }
+            Operation::Mod(a, b) => {
+                children(&[a, b], code);
+                Instruction::Mod
+            }
+            Operation::Constant(value) => Instruction::Constant(*value),
+            Operation::VarX => Instruction::VarX,
+            Operation::VarY => Instruction::VarY,
+            Operation::VarT => Instruction::VarT,
+            Operation::Circle(center_x, center_y) => Instruction::Circle(*center_x, *center_y),
+            Operation::Image(id) => Instruction::Image(*id),
+            Operation::Sine(a) => {
+                children(&[a], code);
+                Instruction::Sine
+            }
+            Operation::Inverse(a) => {
+                children(&[a], code);
+                Instruction::Inverse
+            }
+            Operation::PerChannelMask(m, a, b, threshold) => {
+                children(&[m, a, b], code);
+                Instruction::PerChannelMask(*threshold)
+            }
+            Operation::BinaryMask(m, a, b, threshold) => {
+                children(&[m, a, b], code);
+                Instruction::BinaryMask(*threshold)
+            }
+            Operation::SmoothMix(weight, a, b) => {
+                children(&[weight, a, b], code);
+                Instruction::SmoothMix
+            }
+            Operation::Well(a) => {
+                children(&[a], code);
+                Instruction::Well
+            }
+            Operation::Tent(a) => {
+                children(&[a], code);
+                Instruction::Tent
+            }
+            Operation::RGB(r, g, b) => {
+                children(&[r, g, b], code);
+                Instruction::RGB
+            }
+            Operation::Cos(a) => {
+                children(&[a], code);
+                Instruction::Cos
+            }
+            Operation::Tan(a) => {
+                children(&[a], code);
+                Instruction::Tan
+            }
+            Operation::Atan2(a, b) => {
+                children(&[a, b], code);
+                Instruction::Atan2
+            }
+            Operation::Exp(a) => {
+                children(&[a], code);
+                Instruction::Exp
+            }
+            Operation::Log(a) => {
+                children(&[a], code);
+                Instruction::Log
+            }
+            Operation::Pow(a, b) => {
+                children(&[a, b], code);
+                Instruction::Pow
+            }
+            Operation::Abs(a) => {
+                children(&[a], code);
+                Instruction::Abs
+            }
+            Operation::Sqrt(a) => {
+                children(&[a], code);
+                Instruction::Sqrt
+            }
+            Operation::Min(a, b) => {
+                children(&[a, b], code);
+                Instruction::Min
+            }
+            Operation::Max(a, b) => {
+                children(&[a, b], code);
+                Instruction::Max
+            }
+            Operation::Clamp(a, low, high) => {
+                children(&[a], code);
+                Instruction::Clamp(*low, *high)
+            }
+            Operation::Perlin(a, b, seed) => {
+                children(&[a, b], code);
+                Instruction::Perlin(*seed)
+            }
+            Operation::Simplex(a, b, seed) => {
+                children(&[a, b], code);
+                Instruction::Simplex(*seed)
+            }
+            Operation::ValueNoise(a, b, seed) => {
+                children(&[a, b], code);
+                Instruction::ValueNoise(*seed)
+            }
+            Operation::Worley(a, b, seed) => {
+                children(&[a, b], code);
+                Instruction::Worley(*seed)
+            }
+            Operation::Fbm(a, b, seed, octaves) => {
+                children(&[a, b], code);
+                Instruction::Fbm(*seed, *octaves)
+            }
+            Operation::Mandelbrot(a, b) => {
+                children(&[a, b], code);
+                Instruction::Mandelbrot
+            }
+            Operation::Julia(a, b, c_re, c_im) => {
+                children(&[a, b], code);
+                Instruction::Julia(*c_re, *c_im)
+            }
+            Operation::Rotate(angle, a) => {
+                code.push(Instruction::Rotate(*angle));
+                children(&[a], code);
+                Instruction::EndTransform
+            }
+            Operation::Scale(sx, sy, a) => {
+                code.push(Instruction::Scale(*sx, *sy));
+                children(&[a], code);
+                Instruction::EndTransform
+            }
+            Operation::Translate(dx, dy, a) => {
+                code.push(Instruction::Translate(*dx, *dy));
+                children(&[a], code);
+                Instruction::EndTransform
+            }
+            Operation::Polar(a) => {
+                code.push(Instruction::Polar);
+                children(&[a], code);
+                Instruction::EndTransform
+            }
+            Operation::Mirror(a) => {
+                code.push(Instruction::Mirror);
+                children(&[a], code);
+                Instruction::EndTransform
+            }
+            Operation::Kaleidoscope(n, a) => {
+                code.push(Instruction::Kaleidoscope(*n));
+                children(&[a], code);
+                Instruction::EndTransform
+            }
+            Operation::Warp(dx, dy, a) => {
+                children(&[dx, dy], code);
+                code.push(Instruction::Warp);
+                children(&[a], code);
+                Instruction::EndTransform
+            }
+        };
+        code.push(instruction);
+        max_depth
+    }
+
+    /// Evaluates a single point. Prefer [`CompiledExpr::eval_batch`] for many points.
+    pub fn eval(&self, x: f64, y: f64, t: f64) -> Vec3 {
+        let mut out = [Vec3::new(0.0, 0.0, 0.0)];
+        self.eval_batch(&[x], &[y], t, &mut out);
+        out[0]
+    }
+
+    /// Evaluates the points (`xs[i]`, `ys[i]`) at time `t` into `out[i]`.
+    /// Image leaves evaluate to 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `xs`, `ys` and `out` differ in length.
+    pub fn eval_batch(&self, xs: &[f64], ys: &[f64], t: f64, out: &mut [Vec3]) {
+        self.eval_batch_with(xs, ys, t, &ImageSet::default(), out)
+    }
+
+    /// Like [`CompiledExpr::eval_batch`], sampling image leaves from `images`.
+    pub fn eval_batch_with(
+        &self,
+        xs: &[f64],
+        ys: &[f64],
+        t: f64,
+        images: &ImageSet,
+        out: &mut [Vec3],
+    ) {
+        assert!(
+            xs.len() == ys.len() && xs.len() == out.len(),
+            "batch slices differ in length"
+        );
+        let len = xs.len();
+        let mut stack: Vec<Lanes> = (0..self.max_stack)
+            .map(|_| [vec![0.0; len], vec![0.0; len], vec![0.0; len]])
+            .collect();
+        let mut sp = 0;
+        // Coordinates inside transforms; the innermost is last
+        let mut coords: Vec<(Vec<f64>, Vec<f64>)> = Vec::new();
+
+        for instruction in &self.code {
+            let (xs, ys) = coords
+                .last()
+                .map_or((xs, ys), |(x, y)| (x.as_slice(), y.as_slice()));
+            match *instruction {
+                Instruction::Constant(value) => {
+                    fill(&mut stack[sp], |_| value);
+                    sp += 1;
+                }
+                Instruction::VarX => {
+                    fill(&mut stack[sp], |i| xs[i]);
+                    sp += 1;
+                }
+                Instruction::VarY => {
+                    fill(&mut stack[sp], |i| ys[i]);
+                    sp += 1;
+                }
+                Instruction::VarT => {
+                    fill(&mut stack[sp], |_| t);
+                    sp += 1;
+                }
+                Instruction::Circle(center_x, center_y) => {
+                    fill(&mut stack[sp], |i| {
+                        (xs[i] - center_x).hypot(ys[i] - center_y)
+                    });
+                    sp += 1;
+                }
+                Instruction::Image(id) => {
+                    let lanes = &mut stack[sp];
+                    for i in 0..len {
+                        let value = images.sample(id, xs[i], ys[i]);
+                        lanes[0][i] = value.x();
+                        lanes[1][i] = value.y();
+                        lanes[2][i] = value.z();
+                    }
+                    sp += 1;
+                }
+                Instruction::Sum => {
+                    binary(&mut stack, sp, |a, b| a + b);
+                    sp -= 1;
+                }
+                Instruction::Product => {
+                    binary(&mut stack, sp, |a, b| a * b);
+                    sp -= 1;
+                }
+                Instruction::Mod => {
+                    binary(&mut stack, sp, |a, b| a.rem_euclid(b));
+                    sp -= 1;
+                }
+                Instruction::Sine => unary(&mut stack[sp - 1], f64::sin),
+                Instruction::Inverse => unary(&mut stack[sp - 1], |a| 0.0 - a),
+                Instruction::Well => unary(&mut stack[sp - 1], Operation::well_fn),
+                Instruction::Tent => unary(&mut stack[sp - 1], Operation::tent_fn),
+                Instruction::Cos => unary(&mut stack[sp - 1], f64::cos),
+                Instruction::Tan => unary(&mut stack[sp - 1], Operation::tan_fn),
+                Instruction::Exp => unary(&mut stack[sp - 1], Operation::exp_fn),
+                Instruction::Log => unary(&mut stack[sp - 1], Operation::log_fn),
+                Instruction::Abs => unary(&mut stack[sp - 1], f64::abs),
+                Instruction::Sqrt => unary(&mut stack[sp - 1], Operation::sqrt_fn),
+                Instruction::Clamp(low, high) => {
+                    unary(&mut stack[sp - 1], |a| Operation::clamp_fn(a, low, high))
+                }
+                Instruction::Atan2 => {
+                    binary(&mut stack, sp, Operation::atan2_fn);
+                    sp -= 1;
+                }
+                Instruction::Pow => {
+                    binary(&mut stack, sp, Operation::pow_fn);
+                    sp -= 1;
+                }
+                Instruction::Min => {
+                    binary(&mut stack, sp, f64::min);
+                    sp -= 1;
+                }
+                Instruction::Max => {
+                    binary(&mut stack, sp, f64::max);
+                    sp -= 1;
+                }
+                Instruction::Perlin(seed) => {
+                    binary(&mut stack, sp, |a, b| noise::perlin(a, b, seed));
+                    sp -= 1;
+                }
+                Instruction::Simplex(seed) => {
+                    binary(&mut stack, sp, |a, b| noise::simplex(a, b, seed));
+                    sp -= 1;
+                }
+                Instruction::ValueNoise(seed) => {
+                    binary(&mut stack, sp, |a, b| noise::value(a, b, seed));
+                    sp -= 1;
+                }
+                Instruction::Worley(seed) => {
+                    binary(&mut stack, sp, |a, b| noise::worley(a, b, seed));
+                    sp -= 1;
+                }
+                Instruction::Fbm(seed, octaves) => {
+                    binary(&mut stack, sp, |a, b| noise::fbm(a, b, seed, octaves));
+                    sp -= 1;
+                }
+                Instruction::Mandelbrot => {
+                    binary(&mut stack, sp, |a, b| {
+                        Operation::escape_time_fn(0.0, 0.0, a, b)
+                    });
+                    sp -= 1;
+                }
+                Instruction::Julia(c_re, c_im) => {
+                    binary(&mut stack, sp, |a, b| {
+                        Operation::escape_time_fn(a, b, c_re, c_im)
+                    });
+                    sp -= 1;
+                }
+                Instruction::Rotate(angle) => {
+                    let point = transform(xs, ys, |x, y| Operation::rotate_fn(x, y, angle));
+                    coords.push(point);
+                }
+                Instruction::Scale(sx, sy) => {
+                    let point = transform(xs, ys, |x, y| (x * sx, y * sy));
+                    coords.push(point);
+                }
+                Instruction::Translate(dx, dy) => {
+                    let point = transform(xs, ys, |x, y| (x - dx, y - dy));
+                    coords.push(point);
+                }
+                Instruction::Polar => {
+                    let point = transform(xs, ys, Operation::polar_fn);
+                    coords.push(point);
+                }
+                Instruction::Mirror => {
+                    let point = transform(xs, ys, |x, y| (x.abs(), y));
+                    coords.push(point);
+                }
+                Instruction::Kaleidoscope(n) => {
+                    let point = transform(xs, ys, |x, y| Operation::kaleidoscope_fn(x, y, n));
+                    coords.push(point);
+                }
+                Instruction::Warp => {
+                    let (dx, dy) = (&stack[sp - 2], &stack[sp - 1]);
+                    let point = (
+                        (0..len).map(|i| xs[i] + mean(dx, i)).collect(),
+                        (0..len).map(|i| ys[i] + mean(dy, i)).collect(),
+                    );
+                    coords.push(point);
+                    sp -= 2;
+                }
+                Instruction::EndTransform => {
+                    coords.pop();
+                }
+                Instruction::PerChannelMask(threshold) => {
+                    let (m, a, b) = ternary(&mut stack, sp);
+                    for c in 0..3 {
+                        for i in 0..len {
+                            m[c][i] = if m[c][i] > threshold {
+                                a[c][i]
+                            } else {
+                                b[c][i]
+                            };
+                        }
+                    }
+                    sp -= 2;
+                }
+                Instruction::BinaryMask(threshold) => {
+                    let (m, a, b) = ternary(&mut stack, sp);
+                    for i in 0..len {
+                        let chosen = if length(m, i) > threshold { a } else { b };
+                        for c in 0..3 {
+                            m[c][i] = chosen[c][i];
+                        }
+                    }
+                    sp -= 2;
+                }
+                Instruction::SmoothMix => {
+                    let (weight, a, b) = ternary(&mut stack, sp);
+                    for i in 0..len {
+                        let w = length(weight, i);
+                        for c in 0..3 {
+                            weight[c][i] = (w * a[c][i]) + ((1.0 - w) * b[c][i]);
+                        }
+                    }
+                    sp -= 2;
+                }
+                Instruction::RGB => {
+                    let (r, g, b) = ternary(&mut stack, sp);
+                    for i in 0..len {
+                        let (red, green, blue) = (r[0][i], g[1][i], b[2][i]);
+                        r[0][i] = red;
+                        r[1][i] = green;
+                        r[2][i] = blue;
+                    }
+                    sp -= 2;
+                }
+            }
+        }
+
+        let result = &stack[0];
+        for (i, value) in out.iter_mut().enumerate() {
+            *value = Vec3::new(result[0][i], result[1][i], result[2][i]);
+        }
+    }
+}
+
+impl From<&Operation> for CompiledExpr {
+    fn from(root: &Operation) -> Self {
+        CompiledExpr::compile(root)
+    }
+}
+
+fn fill(lanes: &mut Lanes, value: impl Fn(usize) -> f64) {
+    for i in 0..lanes[0].len() {
+        let v = value(i);
+        lanes[0][i] = v;
+        lanes[1][i] = v;
+        lanes[2][i] = v;
+    }
+}
+
+fn unary(lanes: &mut Lanes, f: impl Fn(f64) -> f64) {
+    for channel in lanes.iter_mut() {
+        for v in channel.iter_mut() {
+            *v = f(*v);
+        }
+    }
+}
+
+/// Combines the top two slots into the lower one.
+fn binary(stack: &mut [Lanes], sp: usize, f: impl Fn(f64, f64) -> f64) {
+    let (lower, upper) = stack.split_at_mut(sp - 1);
+    let (a, b) = (&mut lower[sp - 2], &upper[0]);
+    for c in 0..3 {
+        for (a, b) in a[c].iter_mut().zip(&b[c]) {
+            *a = f(*a, *b);
+        }
+    }
+}
+
+/// Borrows the top three slots; the result is written to the lowest one.
+fn ternary(stack: &mut [Lanes], sp: usize) -> (&mut Lanes, &Lanes, &Lanes) {
+    let (lower, upper) = stack.split_at_mut(sp - 2);
+    (&mut lower[sp - 3], &upper[0], &upper[1])
+}
+
+/// Applies `f` to every point.
+fn transform(xs: &[f64], ys: &[f64], f: impl Fn(f64, f64) -> (f64, f64)) -> (Vec<f64>, Vec<f64>) {
+    xs.iter().zip(ys).map(|(&x, &y)| f(x, y)).unzip()
+}
+
+/// Same as `Operation::mean_fn` for the value at index `i`.
+fn mean(lanes: &Lanes, i: usize) -> f64 {
+    Operation::mean_fn(Vec3::new(lanes[0][i], lanes[1][i], lanes[2][i]))
+}
+
+/// Same as `Vec3::length` for the value at index `i`.
+fn length(lanes: &Lanes, i: usize) -> f64 {
+    Vec3::new(lanes[0][i], lanes[1][i], lanes[2][i]).length()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::{ArtGrammar, OperationWeights, PerrigSongGrammar, RandomArtGrammar};
+    use crate::images::{InputImage, WrapMode};
+    use crate::seed::Seed;
+    use image::{Rgb, RgbImage};
+
+    fn test_images() -> ImageSet {
+        let gradient = RgbImage::from_fn(7, 5, |x, y| Rgb([x as u8 * 36, y as u8 * 60, 200]));
+        ImageSet::new(vec![
+            InputImage::new(gradient.clone(), WrapMode::Repeat),
+            InputImage::new(gradient, WrapMode::Clamp),
+        ])
+    }
+
+    fn bits(v: Vec3) -> [u64; 3] {
+        [v.x().to_bits(), v.y().to_bits(), v.z().to_bits()]
+    }
+
+    fn assert_matches_eval(root: &Operation, images: &ImageSet) {
+        let compiled = CompiledExpr::compile(root);
+        let points: Vec<(f64, f64)> = (0..64)
+            .map(|i| {
+                let i = i as f64;
+                (2.5 * (i * 0.37).sin(), 2.5 * (i * 0.53).cos())
+            })
+            .collect();
+        let (xs, ys): (Vec<f64>, Vec<f64>) = points.iter().copied().unzip();
+        for t in [-0.75, 0.0, 0.6] {
+            let mut out = vec![Vec3::new(0.0, 0.0, 0.0); xs.len()];
+            compiled.eval_batch_with(&xs, &ys, t, images, &mut out);
+            for (&(x, y), value) in points.iter().zip(&out) {
+                assert_eq!(
+                    bits(*value),
+                    bits(root.eval_with(x, y, t, images)),
+                    "at ({}, {}, {}) in {}",
+                    x,
+                    y,
+                    t,
+                    root
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn batches_are_bit_identical_to_eval() {
+        let weights = OperationWeights::uniform().with_image_count(2);
+        let images = test_images();
+        for i in 0..200 {
+            let seed = Seed::from_string(&format!("compiled {}", i));
+            let depth = i % 7;
+            let trees = [
+                RandomArtGrammar::from_seed(&seed)
+                    .with_weights(weights.clone())
+                    .generate_tree(depth),
+                RandomArtGrammar::from_seed(&seed).generate_tree(depth),
+                PerrigSongGrammar::from_seed(&seed).generate_tree(depth),
+            ];
+            for root in &trees {
+                assert_matches_eval(root, &images);
+            }
+        }
+    }
+
+    #[test]
+    fn single_points_match_batches() {
+        let root = Operation::Sine(
+            Operation::Sum(Operation::VarX.into(), Operation::Circle(0.2, -0.1).into()).into(),
+        );
+        let compiled = CompiledExpr::compile(&root);
+        let mut out = [Vec3::new(0.0, 0.0, 0.0)];
+        compiled.eval_batch(&[0.3], &[-0.4], 0.1, &mut out);
+        assert_eq!(bits(compiled.eval(0.3, -0.4, 0.1)), bits(out[0]));
+    }
+}
@@ -0,0 +1,220 @@
+//! Standalone exports that run an expression tree in the browser.
+
+use crate::images::ImageSet;
+use crate::operations::Operation;
+use crate::renderer::RenderSpec;
+use crate::shader::{GlslEs300, ShaderBackend, Shadertoy};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::{ImageFormat, ImageResult};
+use std::io::Cursor;
+
+/// A shader to paste into Shadertoy, see [`Shadertoy`].
+pub fn shadertoy_shader(root: &Operation, spec: &RenderSpec, images: &ImageSet) -> String {
+    Shadertoy.fragment_shader(root, spec, images)
+}
+
+/// A single HTML page rendering `root` with WebGL 2 into a `width`×`height`
+/// canvas. Like the window renderer, `time` counts seconds since the start,
+/// so the page animates `t` the same way. Input images are embedded as PNG
+/// data URLs.
+pub fn html_page(
+    root: &Operation,
+    spec: &RenderSpec,
+    images: &ImageSet,
+    width: usize,
+    height: usize,
+) -> ImageResult<String> {
+    let mut urls = Vec::new();
+    for input in images.images() {
+        let mut png = Cursor::new(Vec::new());
+        input.image().write_to(&mut png, ImageFormat::Png)?;
+        urls.push(format!(
+            "\"data:image/png;base64,{}\"",
+            STANDARD.encode(png.into_inner())
+        ));
+    }
+    let domain = spec.domain;
+    Ok(HTML_PAGE
+        .replace("#WIDTH#", &width.to_string())
+        .replace("#HEIGHT#", &height.to_string())
+        .replace(
+            "#DOMAIN#",
+            &format!(
+                "{:?}, {:?}, {:?}",
+                domain.center_x, domain.center_y, domain.half_extent
+            ),
+        )
+        .replace("#IMAGES#", &urls.join(", "))
+        .replace(
+            "#FRAGMENT_SHADER#",
+            &GlslEs300.fragment_shader(root, spec, images),
+        ))
+}
+
+const HTML_PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Random Art</title>
+<style>
+html, body { margin: 0; height: 100%; background: #000; }
+body { display: flex; align-items: center; justify-content: center; }
+pre { color: #f66; }
+</style>
+</head>
+<body>
+<canvas id="canvas" width="#WIDTH#" height="#HEIGHT#"></canvas>
+<script type="x-shader/x-vertex" id="vertex-shader">#version 300 es
+in vec2 position;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+}
+</script>
+<script type="x-shader/x-fragment" id="fragment-shader">#FRAGMENT_SHADER#</script>
+<script>
+"use strict";
+
+// center x, center y, half extent of the shorter side
+const DOMAIN = [#DOMAIN#];
+// Sampled by image(<id>) leaves, in the order of the ids
+const IMAGES = [#IMAGES#];
+
+function fail(message) {
+    const pre = document.createElement("pre");
+    pre.textContent = message;
+    document.body.replaceChildren(pre);
+    throw new Error(message);
+}
+
+function compile(gl, type, id) {
+    const shader = gl.createShader(type);
+    gl.shaderSource(shader, document.getElementById(id).textContent);
+    gl.compileShader(shader);
+    if (!gl.getShaderParameter(shader, gl.COMPILE_STATUS)) {
+        fail(gl.getShaderInfoLog(shader));
+    }
+    return shader;
+}
+
+function loadImage(url) {
+    return new Promise((resolve, reject) => {
+        const image = new Image();
+        image.onload = () => resolve(image);
+        image.onerror = () => reject(new Error("Failed to load image"));
+        image.src = url;
+    });
+}
+
+async function main() {
+    const canvas = document.getElementById("canvas");
+    const gl = canvas.getContext("webgl2");
+    if (!gl) {
+        fail("WebGL 2 is not available");
+    }
+    const program = gl.createProgram();
+    gl.attachShader(program, compile(gl, gl.VERTEX_SHADER, "vertex-shader"));
+    gl.attachShader(program, compile(gl, gl.FRAGMENT_SHADER, "fragment-shader"));
+    gl.linkProgram(program);
+    if (!gl.getProgramParameter(program, gl.LINK_STATUS)) {
+        fail(gl.getProgramInfoLog(program));
+    }
+    gl.useProgram(program);
+
+    // A single triangle covering the canvas
+    gl.bindBuffer(gl.ARRAY_BUFFER, gl.createBuffer());
+    gl.bufferData(gl.ARRAY_BUFFER, new Float32Array([-1, -1, 3, -1, -1, 3]), gl.STATIC_DRAW);
+    const position = gl.getAttribLocation(program, "position");
+    gl.enableVertexAttribArray(position);
+    gl.vertexAttribPointer(position, 2, gl.FLOAT, false, 0, 0);
+
+    // The shader reads exact texels, with the first image row first
+    gl.pixelStorei(gl.UNPACK_COLORSPACE_CONVERSION_WEBGL, gl.NONE);
+    const images = await Promise.all(IMAGES.map(loadImage));
+    images.forEach((image, id) => {
+        gl.activeTexture(gl.TEXTURE0 + id);
+        gl.bindTexture(gl.TEXTURE_2D, gl.createTexture());
+        gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MIN_FILTER, gl.NEAREST);
+        gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MAG_FILTER, gl.NEAREST);
+        gl.texImage2D(gl.TEXTURE_2D, 0, gl.RGBA8, gl.RGBA, gl.UNSIGNED_BYTE, image);
+        gl.uniform1i(gl.getUniformLocation(program, "image_" + id), id);
+    });
+
+    gl.viewport(0, 0, canvas.width, canvas.height);
+    gl.uniform2f(gl.getUniformLocation(program, "resolution"), canvas.width, canvas.height);
+    gl.uniform3fv(gl.getUniformLocation(program, "domain"), DOMAIN);
+    const time = gl.getUniformLocation(program, "time");
+    const start = performance.now();
+    function frame(now) {
+        gl.uniform1f(time, Math.max(now - start, 0) / 1000);
+        gl.drawArrays(gl.TRIANGLES, 0, 3);
+        requestAnimationFrame(frame);
+    }
+    requestAnimationFrame(frame);
+}
+
+main();
+</script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::{InputImage, WrapMode};
+    use crate::renderer::Domain;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn html_page_embeds_the_shader_domain_and_images() {
+        let root = Operation::Sum(Operation::VarX.into(), Operation::Image(1).into());
+        let spec = RenderSpec {
+            domain: Domain {
+                center_x: 0.5,
+                center_y: -0.25,
+                half_extent: 2.0,
+            },
+            ..RenderSpec::default()
+        };
+        let images: Vec<RgbImage> = vec![
+            RgbImage::from_pixel(2, 1, Rgb([255, 0, 0])),
+            RgbImage::from_fn(3, 2, |x, y| Rgb([x as u8, y as u8, 7])),
+        ];
+        let set = ImageSet::new(
+            images
+                .iter()
+                .map(|image| InputImage::new(image.clone(), WrapMode::Repeat))
+                .collect(),
+        );
+        let page = html_page(&root, &spec, &set, 320, 200).unwrap();
+
+        assert!(page.contains(r#"<canvas id="canvas" width="320" height="200">"#));
+        assert!(page.contains(&format!(
+            r#"id="fragment-shader">{}</script>"#,
+            GlslEs300.fragment_shader(&root, &spec, &set)
+        )));
+        assert!(page.contains("const DOMAIN = [0.5, -0.25, 2.0];"));
+
+        let urls = page
+            .lines()
+            .find_map(|line| line.strip_prefix("const IMAGES = ["))
+            .and_then(|urls| urls.strip_suffix("];"))
+            .unwrap();
+        let decoded: Vec<RgbImage> = urls
+            .split(", ")
+            .map(|url| {
+                let data = url
+                    .trim_matches('"')
+                    .strip_prefix("data:image/png;base64,")
+                    .unwrap();
+                let png = STANDARD.decode(data).unwrap();
+                image::load_from_memory_with_format(&png, ImageFormat::Png)
+                    .unwrap()
+                    .to_rgb8()
+            })
+            .collect();
+        assert_eq!(decoded, images);
+    }
+}
@@ -0,0 +1,473 @@
+use super::{weighted_random_choice, ArtGrammar, OperationKind, WeightedChoice};
+use crate::operations::Operation;
+use crate::seed::Seed;
+use rand_chacha::ChaCha12Rng;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GrammarError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("TOML error: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("RON error: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+
+    #[error("Unknown grammar file extension for {0} (expected .toml or .ron)")]
+    UnknownFormat(String),
+
+    #[error("{location}: {message}")]
+    Invalid { location: String, message: String },
+}
+
+impl GrammarError {
+    fn invalid(location: impl Into<String>, message: impl Into<String>) -> Self {
+        GrammarError::Invalid {
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// A grammar file as written by hand, before validation.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GrammarFile {
+    start: String,
+    rules: BTreeMap<String, RuleFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    productions: Vec<ProductionFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProductionFile {
+    #[serde(default)]
+    op: Option<String>,
+    #[serde(default)]
+    rule: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default = "default_weight")]
+    weight: f64,
+    #[serde(default)]
+    range: Option<Vec<f64>>,
+    #[serde(default)]
+    depth_cost: Option<usize>,
+    #[serde(default)]
+    min_depth: Option<usize>,
+    #[serde(default)]
+    max_depth: Option<usize>,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone)]
+enum ProductionKind {
+    /// Expands another rule, at the remaining depth minus the depth cost.
+    Rule(usize),
+    Op {
+        op: OperationKind,
+        args: Vec<usize>,
+        range: (f64, f64),
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Production {
+    kind: ProductionKind,
+    weight: f64,
+    depth_cost: usize,
+    min_depth: usize,
+    max_depth: Option<usize>,
+}
+
+impl Production {
+    fn is_eligible(&self, depth: usize) -> bool {
+        depth >= self.min_depth && self.max_depth.is_none_or(|max| depth <= max)
+    }
+}
+
+/// A validated grammar loaded from a TOML or RON description.
+///
+/// A grammar has a `start` rule and a set of named rules, each with weighted
+/// productions. A production either expands another rule (`rule = "A"`) or
+/// produces an operation (`op = "sum"`) whose `args` name the rules that
+/// generate its children. Optional fields:
+///
+/// - `weight` (default 1): relative probability among eligible productions.
+/// - `range` (default `[-1, 1]`): range of random parameters, i.e. the value
+///   of `constant`, the center of `circle`, the threshold of masks, the bounds
+///   of `clamp` and the constant of `julia`. For `image`, the range of image
+///   ids, which defaults to `[0, 0]`.
+/// - `depth_cost` (default 1 for operations with arguments, 0 otherwise):
+///   how much of the remaining depth the children, or the expanded rule, lose.
+/// - `min_depth` (default `depth_cost`) and `max_depth` (default unbounded):
+///   the production is only chosen while the remaining depth is in range.
+///
+/// Validation guarantees that generation always terminates: every rule has a
+/// production for any remaining depth, and no rule can reach itself without
+/// spending depth.
+#[derive(Debug, Clone)]
+pub struct GrammarDefinition {
+    names: Vec<String>,
+    rules: Vec<Vec<Production>>,
+    start: usize,
+}
+
+impl GrammarDefinition {
+    /// Loads a grammar, choosing TOML or RON from the file extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GrammarError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&source),
+            Some("ron") => Self::from_ron_str(&source),
+            _ => Err(GrammarError::UnknownFormat(path.display().to_string())),
+        }
+    }
+
+    pub fn from_toml_str(source: &str) -> Result<Self, GrammarError> {
+        Self::from_file(toml::from_str(source)?)
+    }
+
+    /// Parses a RON grammar. Optional fields can be written without `Some(...)`.
+    pub fn from_ron_str(source: &str) -> Result<Self, GrammarError> {
+        let options = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME);
+        Self::from_file(options.from_str(source)?)
+    }
+
+    pub fn rule_names(&self) -> &[String] {
+        &self.names
+    }
+
+    fn from_file(file: GrammarFile) -> Result<Self, GrammarError> {
+        let names: Vec<String> = file.rules.keys().cloned().collect();
+        let index_of = |name: &str| names.iter().position(|n| n == name);
+
+        let start = index_of(&file.start).ok_or_else(|| {
+            GrammarError::invalid("start", format!("unknown rule `{}`", file.start))
+        })?;
+
+        let mut rules = Vec::with_capacity(names.len());
+        for (name, rule) in &file.rules {
+            if rule.productions.is_empty() {
+                return Err(GrammarError::invalid(
+                    format!("rules.{}", name),
+                    "rule has no productions",
+                ));
+            }
+            let mut productions = Vec::with_capacity(rule.productions.len());
+            for (i, production) in rule.productions.iter().enumerate() {
+                let location = format!("rules.{}.productions[{}]", name, i);
+                productions.push(Self::validate_production(production, &index_of, &location)?);
+            }
+            Self::check_depth_coverage(name, &productions)?;
+            rules.push(productions);
+        }
+
+        let definition = GrammarDefinition {
+            names,
+            rules,
+            start,
+        };
+        definition.check_zero_cost_cycles()?;
+        Ok(definition)
+    }
+
+    fn validate_production(
+        production: &ProductionFile,
+        index_of: &impl Fn(&str) -> Option<usize>,
+        location: &str,
+    ) -> Result<Production, GrammarError> {
+        let invalid = |message: String| GrammarError::invalid(location, message);
+        let rule_index =
+            |name: &str| index_of(name).ok_or_else(|| invalid(format!("unknown rule `{}`", name)));
+
+        if !(production.weight.is_finite() && production.weight >= 0.0) {
+            return Err(invalid(format!(
+                "weight must be a non-negative number, got {}",
+                production.weight
+            )));
+        }
+
+        let kind = match (&production.op, &production.rule) {
+            (Some(_), Some(_)) => {
+                return Err(invalid("set either `op` or `rule`, not both".into()))
+            }
+            (None, None) => return Err(invalid("missing `op` or `rule`".into())),
+            (None, Some(rule)) => {
+                if !production.args.is_empty() || production.range.is_some() {
+                    return Err(invalid(
+                        "a `rule` production takes no `args` or `range`".into(),
+                    ));
+                }
+                ProductionKind::Rule(rule_index(rule)?)
+            }
+            (Some(name), None) => {
+                let op = OperationKind::from_name(name).ok_or_else(|| {
+                    let known: Vec<&str> = OperationKind::ALL.iter().map(|op| op.name()).collect();
+                    invalid(format!(
+                        "unknown op `{}` (expected one of {})",
+                        name,
+                        known.join(", ")
+                    ))
+                })?;
+                if production.args.len() != op.arity() {
+                    return Err(invalid(format!(
+                        "`{}` takes {} arguments, got {}",
+                        name,
+                        op.arity(),
+                        production.args.len()
+                    )));
+                }
+                let range = match production.range.as_deref() {
+                    None => op.default_range(),
+                    Some(_) if !op.has_range() => {
+                        return Err(invalid(format!(
+                            "`{}` has no random parameters to take a `range`",
+                            name
+                        )))
+                    }
+                    Some(&[lo, hi]) if lo.is_finite() && hi.is_finite() && lo <= hi => (lo, hi),
+                    Some(range) => {
+                        return Err(invalid(format!(
+                        "range must be two finite numbers [low, high] with low <= high, got {:?}",
+                        range
+                    )))
+                    }
+                };
+                let args = production
+                    .args
+                    .iter()
+                    .map(|arg| rule_index(arg))
+                    .collect::<Result<_, _>>()?;
+                ProductionKind::Op { op, args, range }
+            }
+        };
+
+        let has_args = matches!(&kind, ProductionKind::Op { args, .. } if !args.is_empty());
+        let depth_cost = production
+            .depth_cost
+            .unwrap_or(if has_args { 1 } else { 0 });
+        let min_depth = production.min_depth.unwrap_or(depth_cost);
+        if min_depth < depth_cost {
+            return Err(invalid(format!(
+                "min_depth ({}) must be at least depth_cost ({})",
+                min_depth, depth_cost
+            )));
+        }
+        if let Some(max_depth) = production.max_depth {
+            if max_depth < min_depth {
+                return Err(invalid(format!(
+                    "max_depth ({}) is below min_depth ({})",
+                    max_depth, min_depth
+                )));
+            }
+        }
+
+        Ok(Production {
+            kind,
+            weight: production.weight,
+            depth_cost,
+            min_depth,
+            max_depth: production.max_depth,
+        })
+    }
+
+    /// Checks that positive-weight productions cover every remaining depth.
+    fn check_depth_coverage(name: &str, productions: &[Production]) -> Result<(), GrammarError> {
+        let mut ranges: Vec<(usize, Option<usize>)> = productions
+            .iter()
+            .filter(|p| p.weight > 0.0)
+            .map(|p| (p.min_depth, p.max_depth))
+            .collect();
+        ranges.sort_by_key(|(min, _)| *min);
+
+        let mut covered = 0;
+        for (min, max) in ranges {
+            if min > covered {
+                break;
+            }
+            match max {
+                None => return Ok(()),
+                Some(max) => covered = covered.max(max + 1),
+            }
+        }
+        Err(GrammarError::invalid(
+            format!("rules.{}", name),
+            format!(
+                "no production with positive weight can be chosen at depth {}",
+                covered
+            ),
+        ))
+    }
+
+    /// Rejects rules that can reach themselves through productions with a
+    /// `depth_cost` of 0, which could expand forever.
+    fn check_zero_cost_cycles(&self) -> Result<(), GrammarError> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            Unvisited,
+            Visiting,
+            Done,
+        }
+
+        fn visit(
+            grammar: &GrammarDefinition,
+            rule: usize,
+            states: &mut [State],
+        ) -> Result<(), GrammarError> {
+            states[rule] = State::Visiting;
+            for (i, production) in grammar.rules[rule].iter().enumerate() {
+                if production.depth_cost > 0 {
+                    continue;
+                }
+                let targets = match &production.kind {
+                    ProductionKind::Rule(target) => std::slice::from_ref(target),
+                    ProductionKind::Op { args, .. } => args.as_slice(),
+                };
+                for &target in targets {
+                    match states[target] {
+                        State::Visiting => {
+                            return Err(GrammarError::invalid(
+                                format!("rules.{}.productions[{}]", grammar.names[rule], i),
+                                format!(
+                                    "rule `{}` can expand forever without spending depth",
+                                    grammar.names[target]
+                                ),
+                            ))
+                        }
+                        State::Unvisited => visit(grammar, target, states)?,
+                        State::Done => {}
+                    }
+                }
+            }
+            states[rule] = State::Done;
+            Ok(())
+        }
+
+        let mut states = vec![State::Unvisited; self.rules.len()];
+        for rule in 0..self.rules.len() {
+            if states[rule] == State::Unvisited {
+                visit(self, rule, &mut states)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Grammar whose rules and weights come from a [`GrammarDefinition`] file.
+pub struct ConfigurableGrammar {
+    definition: GrammarDefinition,
+    rng: ChaCha12Rng,
+}
+
+impl ConfigurableGrammar {
+    pub fn new(definition: GrammarDefinition, seed: &Seed) -> Self {
+        ConfigurableGrammar {
+            definition,
+            rng: seed.rng(),
+        }
+    }
+
+    fn generate_rule(&mut self, rule: usize, depth: usize) -> Operation {
+        let choices: Vec<WeightedChoice<usize>> = self.definition.rules[rule]
+            .iter()
+            .enumerate()
+            .filter(|(_, production)| production.is_eligible(depth))
+            .map(|(i, production)| WeightedChoice::new(i, production.weight))
+            .collect();
+        let index = *weighted_random_choice(&mut self.rng, &choices);
+
+        let production = &self.definition.rules[rule][index];
+        let depth_cost = production.depth_cost;
+        match production.kind.clone() {
+            ProductionKind::Rule(target) => self.generate_rule(target, depth - depth_cost),
+            ProductionKind::Op { op, args, range } => {
+                let children = args
+                    .iter()
+                    .map(|&arg| self.generate_rule(arg, depth - depth_cost))
+                    .collect();
+                op.build(children, &mut self.rng, range)
+            }
+        }
+    }
+}
+
+impl ArtGrammar for ConfigurableGrammar {
+    fn generate_tree(&mut self, depth: usize) -> Operation {
+        self.generate_rule(self.definition.start, depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(source: &str, depth: usize) -> Operation {
+        let definition = GrammarDefinition::from_toml_str(source).unwrap();
+        ConfigurableGrammar::new(definition, &Seed::from_string("grammar")).generate_tree(depth)
+    }
+
+    #[test]
+    fn rule_productions_spend_their_depth_cost() {
+        let source = r#"
+            start = "C"
+            [rules.C]
+            productions = [
+                { rule = "C", depth_cost = 1 },
+                { op = "x", max_depth = 0 },
+            ]
+        "#;
+        assert_eq!(generate(source, 3), Operation::VarX);
+    }
+
+    #[test]
+    fn rejects_zero_cost_cycles() {
+        let source = r#"
+            start = "A"
+            [rules.A]
+            productions = [{ rule = "B" }, { op = "x" }]
+            [rules.B]
+            productions = [{ op = "sin", args = ["A"], depth_cost = 0 }]
+        "#;
+        let err = GrammarDefinition::from_toml_str(source).unwrap_err();
+        assert!(err.to_string().contains("can expand forever"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_uncovered_depths() {
+        let source = r#"
+            start = "A"
+            [rules.A]
+            productions = [{ op = "sin", args = ["A"] }]
+        "#;
+        let err = GrammarDefinition::from_toml_str(source).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rules.A: no production with positive weight can be chosen at depth 0"
+        );
+    }
+
+    #[test]
+    fn bundled_grammars_load() {
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+        for name in ["grammars/perrig_song.toml", "grammars/random_art.ron"] {
+            GrammarDefinition::load(manifest.join(name)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use crate::operations::Operation;
+use crate::seed::Seed;
+use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+
+#[cfg(feature = "serde")]
+mod configurable;
+mod weights;
+
+#[cfg(feature = "serde")]
+pub use configurable::{ConfigurableGrammar, GrammarDefinition, GrammarError};
+pub use weights::{OperationKind, OperationWeights};
+
+pub trait ArtGrammar {
+    fn generate_tree(&mut self, depth: usize) -> Operation;
+}
+
+pub struct RandomArtGrammar {
+    rng: ChaCha12Rng,
+    weights: Option<OperationWeights>,
+}
+
+impl RandomArtGrammar {
+    pub fn new(seed: u64) -> Self {
+        RandomArtGrammar {
+            rng: ChaCha12Rng::seed_from_u64(seed),
+            weights: None,
+        }
+    }
+
+    pub fn from_seed(seed: &Seed) -> Self {
+        RandomArtGrammar {
+            rng: seed.rng(),
+            weights: None,
+        }
+    }
+
+    /// Chooses operations with the given weights: leaves at depth 0, operations
+    /// with children above (or leaves, if no such operation has a weight).
+    ///
+    /// Without weights the grammar keeps its original seed to tree mapping.
+    pub fn with_weights(mut self, weights: OperationWeights) -> Self {
+        self.weights = Some(weights);
+        self
+    }
+
+    fn rand_leaf(&mut self) -> Operation {
+        let choices = [
+            Operation::VarX,
+            Operation::VarY,
+            Operation::VarT,
+            Operation::Constant(self.rng.gen_range(-1.0..=1.0)),
+            Operation::Circle(
+                self.rng.gen_range(-1.0..=1.0),
+                self.rng.gen_range(-1.0..=1.0),
+            ),
+        ];
+        choices.choose(&mut self.rng).unwrap().clone()
+    }
+
+    fn rand_internal_op(&mut self, depth: usize) -> Operation {
+        let choices = [
+            |g: &mut Self, depth| {
+                Operation::Sum(
+                    g.generate_tree(depth - 1).into(),
+                    g.generate_tree(depth - 1).into(),
+                )
+            },
+            |g: &mut Self, depth| Operation::Sine(g.generate_tree(depth - 1).into()),
+            |g: &mut Self, depth| {
+                Operation::PerChannelMask(
+                    g.generate_tree(depth - 1).into(),
+                    g.generate_tree(depth - 1).into(),
+                    g.generate_tree(depth - 1).into(),
+                    g.rng.gen_range(-1.0..=1.0),
+                )
+            },
+            |g: &mut Self, depth| {
+                Operation::BinaryMask(
+                    g.generate_tree(depth - 1).into(),
+                    g.generate_tree(depth - 1).into(),
+                    g.generate_tree(depth - 1).into(),
+                    g.rng.gen_range(-1.0..=1.0),
+                )
+            },
+            |g: &mut Self, depth| {
+                Operation::SmoothMix(
+                    g.generate_tree(depth - 1).into(),
+                    g.generate_tree(depth - 1).into(),
+                    g.generate_tree(depth - 1).into(),
+                )
+            },
+            |g: &mut Self, depth| Operation::Well(g.generate_tree(depth - 1).into()),
+            |g: &mut Self, depth| Operation::Tent(g.generate_tree(depth - 1).into()),
+            |g: &mut Self, depth| {
+                Operation::Product(
+                    g.generate_tree(depth - 1).into(),
+                    g.generate_tree(depth - 1).into(),
+                )
+            },
+            |g: &mut Self, depth| Operation::Inverse(g.generate_tree(depth - 1).into()),
+            |g: &mut Self, depth| {
+                Operation::Mod(
+                    g.generate_tree(depth - 1).into(),
+                    g.generate_tree(depth - 1).into(),
+                )
+            },
+        ];
+
+        let op_fn = choices.choose(&mut self.rng).unwrap();
+        op_fn(self, depth)
+    }
+}
+
+impl ArtGrammar for RandomArtGrammar {
+    fn generate_tree(&mut self, depth: usize) -> Operation {
+        if let Some(weights) = self.weights.clone() {
+            generate_weighted(&mut self.rng, &weights, depth, false)
+        } else if depth == 0 {
+            self.rand_leaf()
+        } else {
+            self.rand_internal_op(depth)
+        }
+    }
+}
+
+/// Generates a tree by weighted choice of operations. Leaves are chosen at
+/// depth 0, and above it only if `inner_leaves` is set or no operation with
+/// children has a positive weight.
+fn generate_weighted(
+    rng: &mut ChaCha12Rng,
+    weights: &OperationWeights,
+    depth: usize,
+    inner_leaves: bool,
+) -> Operation {
+    let mut choices = weights.choices(|kind| inner_leaves || kind.arity() > 0);
+    if depth == 0 || choices.is_empty() {
+        choices = weights.choices(|kind| kind.arity() == 0);
+    }
+    let kind = *weighted_random_choice(rng, &choices);
+    let children = (0..kind.arity())
+        .map(|_| generate_weighted(rng, weights, depth - 1, inner_leaves))
+        .collect();
+    kind.build(children, rng, weights.range(kind))
+}
+
+// Structure to represent a choice with its probability
+pub struct WeightedChoice<T> {
+    pub choice: T,
+    pub weight: f64,
+}
+
+impl<T> WeightedChoice<T> {
+    pub fn new(choice: T, weight: f64) -> Self {
+        WeightedChoice { choice, weight }
+    }
+}
+
+/// Picks one of `choices` with probability proportional to its weight,
+/// consuming exactly one `f64` draw from `rng`.
+pub fn weighted_random_choice<'a, T>(
+    rng: &mut impl Rng,
+    choices: &'a [WeightedChoice<T>],
+) -> &'a T {
+    let total_weight: f64 = choices.iter().map(|c| c.weight).sum();
+    let mut random_weight = rng.gen_range(0.0..total_weight);
+
+    for choice in choices {
+        if random_weight < choice.weight {
+            return &choice.choice;
+        }
+        random_weight -= choice.weight;
+    }
+
+    &choices.last().unwrap().choice
+}
+
+// Source: https://users.ece.cmu.edu/~adrian/projects/validation/validation.pdf
+pub struct PerrigSongGrammar {
+    rng: ChaCha12Rng,
+    weights: Option<OperationWeights>,
+}
+
+impl PerrigSongGrammar {
+    pub fn new(seed: u64) -> Self {
+        PerrigSongGrammar {
+            rng: ChaCha12Rng::seed_from_u64(seed),
+            weights: None,
+        }
+    }
+
+    pub fn from_seed(seed: &Seed) -> Self {
+        PerrigSongGrammar {
+            rng: seed.rng(),
+            weights: None,
+        }
+    }
+
+    /// Deepest tree [`ArtGrammar::generate_tree`] builds without weights. The
+    /// three subtrees of a tree this deep use fewer than 2^68 generator words,
+    /// the period of the generator, so seeking through them keeps the seed to
+    /// tree mapping of the original implementation.
+    pub const MAX_DEPTH: usize = 31;
+
+    /// Chooses the operations of `C` with the given weights: leaves at depth 0,
+    /// any operation above. The root stays `RGB(C, C, C)`.
+    ///
+    /// Without weights the grammar keeps its original seed to tree mapping.
+    pub fn with_weights(mut self, weights: OperationWeights) -> Self {
+        self.weights = Some(weights);
+        self
+    }
+
+    fn generate_a(&mut self) -> Operation {
+        let choices = [
+            WeightedChoice::new(Operation::Constant(self.rng.gen_range(-1.0..=1.0)), 1.0),
+            WeightedChoice::new(Operation::VarX, 1.0),
+            WeightedChoice::new(Operation::VarY, 1.0),
+            WeightedChoice::new(Operation::VarT, 1.0),
+        ];
+
+        weighted_random_choice(&mut self.rng, &choices).clone()
+    }
+
+    /// Builds only the chosen alternative of `C`.
+    ///
+    /// The original implementation generated all three alternatives before
+    /// choosing one, drawing from the generator in this order:
+    ///
+    /// `A | Sum lhs | Sum rhs | Product lhs | Product rhs | choice`
+    ///
+    /// Every draw consumes exactly one `u64`, so the size of each block only
+    /// depends on the depth. We seek to the choice first, then back to the
+    /// block of the chosen alternative, and finally past the end. This keeps
+    /// the seed to tree mapping of the original while doing work linear in
+    /// the size of the resulting tree, as long as the positions stay within
+    /// the generator's period (see [`PerrigSongGrammar::MAX_DEPTH`]).
+    fn generate_c(&mut self, depth: usize) -> Operation {
+        if depth == 0 {
+            return self.generate_a();
+        }
+
+        let start = self.rng.get_word_pos();
+        let a_words = Self::words_used_by_c(0);
+        let child_words = Self::words_used_by_c(depth - 1);
+        let position = |offset: u128| {
+            start
+                .checked_add(offset)
+                .expect("generator position overflows")
+        };
+        let end = position(Self::words_used_by_c(depth));
+
+        self.rng.set_word_pos(end - WORDS_PER_DRAW);
+        let choices = [
+            WeightedChoice::new(Alternative::A, 1.0),
+            WeightedChoice::new(Alternative::Sum, 2.0),
+            WeightedChoice::new(Alternative::Product, 2.0),
+        ];
+        let op = match weighted_random_choice(&mut self.rng, &choices) {
+            Alternative::A => {
+                self.rng.set_word_pos(start);
+                self.generate_a()
+            }
+            Alternative::Sum => {
+                self.rng.set_word_pos(position(a_words));
+                Operation::Sum(
+                    self.generate_c(depth - 1).into(),
+                    self.generate_c(depth - 1).into(),
+                )
+            }
+            Alternative::Product => {
+                self.rng.set_word_pos(position(a_words + 2 * child_words));
+                Operation::Product(
+                    self.generate_c(depth - 1).into(),
+                    self.generate_c(depth - 1).into(),
+                )
+            }
+        };
+        self.rng.set_word_pos(end);
+        op
+    }
+
+    /// Number of generator words consumed by `generate_c(depth)`: `A` takes two
+    /// draws, and each deeper level adds four subtrees and the choice, so
+    /// `3 * 4^depth - 1` draws in total.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the count does not fit in a `u128`, far above `MAX_DEPTH`.
+    fn words_used_by_c(depth: usize) -> u128 {
+        let mut draws: u128 = 2;
+        for _ in 0..depth {
+            draws = draws
+                .checked_mul(4)
+                .and_then(|draws| draws.checked_add(3))
+                .expect("generator word count overflows");
+        }
+        draws * WORDS_PER_DRAW
+    }
+}
+
+/// ChaCha produces 32-bit words, and every `u64` draw consumes two of them.
+const WORDS_PER_DRAW: u128 = 2;
+
+#[derive(Clone, Copy)]
+enum Alternative {
+    A,
+    Sum,
+    Product,
+}
+
+impl ArtGrammar for PerrigSongGrammar {
+    /// # Panics
+    ///
+    /// Panics without weights if `depth` is above [`PerrigSongGrammar::MAX_DEPTH`].
+    fn generate_tree(&mut self, depth: usize) -> Operation {
+        if let Some(weights) = self.weights.clone() {
+            let mut generate_c = || generate_weighted(&mut self.rng, &weights, depth, true);
+            return Operation::RGB(
+                generate_c().into(),
+                generate_c().into(),
+                generate_c().into(),
+            );
+        }
+        assert!(
+            depth <= Self::MAX_DEPTH,
+            "depth {} is above the maximum of {}",
+            depth,
+            Self::MAX_DEPTH
+        );
+        Operation::RGB(
+            self.generate_c(depth).into(),
+            self.generate_c(depth).into(),
+            self.generate_c(depth).into(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Words in the period of ChaCha's 64-bit block counter, with 16 words per
+    /// block. Seeking past it wraps around.
+    const PERIOD_WORDS: u128 = 1 << 68;
+
+    /// The original `PerrigSongGrammar`, which generated every alternative of
+    /// `C` before choosing one. Seeds must keep producing its trees.
+    struct EagerPerrigSong {
+        rng: ChaCha12Rng,
+    }
+
+    impl EagerPerrigSong {
+        fn generate_a(&mut self) -> Operation {
+            let choices = [
+                WeightedChoice::new(Operation::Constant(self.rng.gen_range(-1.0..=1.0)), 1.0),
+                WeightedChoice::new(Operation::VarX, 1.0),
+                WeightedChoice::new(Operation::VarY, 1.0),
+                WeightedChoice::new(Operation::VarT, 1.0),
+            ];
+            weighted_random_choice(&mut self.rng, &choices).clone()
+        }
+
+        fn generate_c(&mut self, depth: usize) -> Operation {
+            if depth == 0 {
+                return self.generate_a();
+            }
+            let choices = [
+                WeightedChoice::new(self.generate_a(), 1.0),
+                WeightedChoice::new(
+                    Operation::Sum(
+                        self.generate_c(depth - 1).into(),
+                        self.generate_c(depth - 1).into(),
+                    ),
+                    2.0,
+                ),
+                WeightedChoice::new(
+                    Operation::Product(
+                        self.generate_c(depth - 1).into(),
+                        self.generate_c(depth - 1).into(),
+                    ),
+                    2.0,
+                ),
+            ];
+            weighted_random_choice(&mut self.rng, &choices).clone()
+        }
+
+        fn generate_tree(&mut self, depth: usize) -> Operation {
+            Operation::RGB(
+                self.generate_c(depth).into(),
+                self.generate_c(depth).into(),
+                self.generate_c(depth).into(),
+            )
+        }
+    }
+
+    #[test]
+    fn max_depth_fits_in_the_generator_period() {
+        let tree_words = |depth| 3 * PerrigSongGrammar::words_used_by_c(depth);
+        assert!(tree_words(PerrigSongGrammar::MAX_DEPTH) <= PERIOD_WORDS);
+        assert!(tree_words(PerrigSongGrammar::MAX_DEPTH + 1) > PERIOD_WORDS);
+    }
+
+    #[test]
+    fn seeks_at_max_depth_without_overflow() {
+        // `C` chooses `A` with probability 1/5, so for some seeds all three
+        // channels are leaves even at this depth
+        let words = PerrigSongGrammar::words_used_by_c(PerrigSongGrammar::MAX_DEPTH);
+        let seed = (0..)
+            .map(|i| Seed::from_string(&format!("max depth {}", i)))
+            .find(|seed| {
+                let mut rng = seed.rng();
+                (1..=3).all(|channel| {
+                    rng.set_word_pos(channel * words - WORDS_PER_DRAW);
+                    rng.gen_range(0.0..5.0) < 1.0
+                })
+            })
+            .unwrap();
+        let mut grammar = PerrigSongGrammar::from_seed(&seed);
+        let tree = grammar.generate_tree(PerrigSongGrammar::MAX_DEPTH);
+        assert_eq!(tree.node_count(), 4);
+        assert_eq!(grammar.rng.get_word_pos(), 3 * words);
+    }
+
+    #[test]
+    #[should_panic(expected = "above the maximum")]
+    fn rejects_depths_above_max_depth() {
+        PerrigSongGrammar::new(0).generate_tree(PerrigSongGrammar::MAX_DEPTH + 1);
+    }
+
+    #[test]
+    fn perrig_song_matches_eager_generation() {
+        // The eager version takes time exponential in the depth
+        for i in 0..300 {
+            let seed = Seed::from_string(&format!("perrig song {}", i));
+            let max_depth = if i < 20 { 6 } else { 4 };
+            for depth in 0..=max_depth {
+                let mut lazy = PerrigSongGrammar::from_seed(&seed);
+                let mut eager = EagerPerrigSong { rng: seed.rng() };
+                assert_eq!(
+                    lazy.generate_tree(depth),
+                    eager.generate_tree(depth),
+                    "seed {}, depth {}",
+                    i,
+                    depth
+                );
+                // Both leave the generator at the same position
+                assert_eq!(lazy.rng.gen::<u64>(), eager.rng.gen::<u64>());
+            }
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use super::WeightedChoice;
+use crate::noise;
+use crate::operations::Operation;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// Kinds of operations a grammar can produce, named as in the textual form of trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    Constant,
+    VarX,
+    VarY,
+    VarT,
+    Circle,
+    Sum,
+    Product,
+    Mod,
+    Sine,
+    Inverse,
+    PerChannelMask,
+    BinaryMask,
+    SmoothMix,
+    Well,
+    Tent,
+    RGB,
+    Cos,
+    Tan,
+    Atan2,
+    Exp,
+    Log,
+    Pow,
+    Abs,
+    Sqrt,
+    Min,
+    Max,
+    Clamp,
+    Perlin,
+    Simplex,
+    ValueNoise,
+    Worley,
+    Fbm,
+    Mandelbrot,
+    Julia,
+    Rotate,
+    Scale,
+    Translate,
+    Polar,
+    Warp,
+    Mirror,
+    Kaleidoscope,
+    Image,
+}
+
+impl OperationKind {
+    pub const ALL: [OperationKind; 42] = [
+        OperationKind::Constant,
+        OperationKind::VarX,
+        OperationKind::VarY,
+        OperationKind::VarT,
+        OperationKind::Circle,
+        OperationKind::Sum,
+        OperationKind::Product,
+        OperationKind::Mod,
+        OperationKind::Sine,
+        OperationKind::Inverse,
+        OperationKind::PerChannelMask,
+        OperationKind::BinaryMask,
+        OperationKind::SmoothMix,
+        OperationKind::Well,
+        OperationKind::Tent,
+        OperationKind::RGB,
+        OperationKind::Cos,
+        OperationKind::Tan,
+        OperationKind::Atan2,
+        OperationKind::Exp,
+        OperationKind::Log,
+        OperationKind::Pow,
+        OperationKind::Abs,
+        OperationKind::Sqrt,
+        OperationKind::Min,
+        OperationKind::Max,
+        OperationKind::Clamp,
+        OperationKind::Perlin,
+        OperationKind::Simplex,
+        OperationKind::ValueNoise,
+        OperationKind::Worley,
+        OperationKind::Fbm,
+        OperationKind::Mandelbrot,
+        OperationKind::Julia,
+        OperationKind::Rotate,
+        OperationKind::Scale,
+        OperationKind::Translate,
+        OperationKind::Polar,
+        OperationKind::Warp,
+        OperationKind::Mirror,
+        OperationKind::Kaleidoscope,
+        OperationKind::Image,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OperationKind::Constant => "constant",
+            OperationKind::VarX => "x",
+            OperationKind::VarY => "y",
+            OperationKind::VarT => "t",
+            OperationKind::Circle => "circle",
+            OperationKind::Sum => "sum",
+            OperationKind::Product => "product",
+            OperationKind::Mod => "mod",
+            OperationKind::Sine => "sin",
+            OperationKind::Inverse => "inverse",
+            OperationKind::PerChannelMask => "per_channel_mask",
+            OperationKind::BinaryMask => "binary_mask",
+            OperationKind::SmoothMix => "smooth_mix",
+            OperationKind::Well => "well",
+            OperationKind::Tent => "tent",
+            OperationKind::RGB => "rgb",
+            OperationKind::Cos => "cos",
+            OperationKind::Tan => "tan",
+            OperationKind::Atan2 => "atan2",
+            OperationKind::Exp => "exp",
+            OperationKind::Log => "log",
+            OperationKind::Pow => "pow",
+            OperationKind::Abs => "abs",
+            OperationKind::Sqrt => "sqrt",
+            OperationKind::Min => "min",
+            OperationKind::Max => "max",
+            OperationKind::Clamp => "clamp",
+            OperationKind::Perlin => "perlin",
+            OperationKind::Simplex => "simplex",
+            OperationKind::ValueNoise => "value_noise",
+            OperationKind::Worley => "worley",
+            OperationKind::Fbm => "fbm",
+            OperationKind::Mandelbrot => "mandelbrot",
+            OperationKind::Julia => "julia",
+            OperationKind::Rotate => "rotate",
+            OperationKind::Scale => "scale",
+            OperationKind::Translate => "translate",
+            OperationKind::Polar => "polar",
+            OperationKind::Warp => "warp",
+            OperationKind::Mirror => "mirror",
+            OperationKind::Kaleidoscope => "kaleidoscope",
+            OperationKind::Image => "image",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// Number of child operations.
+    pub fn arity(self) -> usize {
+        match self {
+            OperationKind::Constant
+            | OperationKind::VarX
+            | OperationKind::VarY
+            | OperationKind::VarT
+            | OperationKind::Circle
+            | OperationKind::Image => 0,
+            OperationKind::Sine
+            | OperationKind::Inverse
+            | OperationKind::Well
+            | OperationKind::Tent
+            | OperationKind::Cos
+            | OperationKind::Tan
+            | OperationKind::Exp
+            | OperationKind::Log
+            | OperationKind::Abs
+            | OperationKind::Sqrt
+            | OperationKind::Clamp
+            | OperationKind::Rotate
+            | OperationKind::Scale
+            | OperationKind::Translate
+            | OperationKind::Polar
+            | OperationKind::Mirror
+            | OperationKind::Kaleidoscope => 1,
+            OperationKind::Sum
+            | OperationKind::Product
+            | OperationKind::Mod
+            | OperationKind::Atan2
+            | OperationKind::Pow
+            | OperationKind::Min
+            | OperationKind::Max
+            | OperationKind::Perlin
+            | OperationKind::Simplex
+            | OperationKind::ValueNoise
+            | OperationKind::Worley
+            | OperationKind::Fbm
+            | OperationKind::Mandelbrot
+            | OperationKind::Julia => 2,
+            OperationKind::PerChannelMask
+            | OperationKind::BinaryMask
+            | OperationKind::SmoothMix
+            | OperationKind::RGB
+            | OperationKind::Warp => 3,
+        }
+    }
+
+    /// Whether the operation has random parameters, drawn from a range when it is built.
+    pub fn has_range(self) -> bool {
+        matches!(
+            self,
+            OperationKind::Constant
+                | OperationKind::Circle
+                | OperationKind::PerChannelMask
+                | OperationKind::BinaryMask
+                | OperationKind::Clamp
+                | OperationKind::Julia
+                | OperationKind::Rotate
+                | OperationKind::Scale
+                | OperationKind::Translate
+                | OperationKind::Image
+        )
+    }
+
+    /// The range of random parameters when none is given. For images, the
+    /// range of ids to choose from.
+    pub fn default_range(self) -> (f64, f64) {
+        match self {
+            OperationKind::Image => (0.0, 0.0),
+            _ => (-1.0, 1.0),
+        }
+    }
+
+    /// Builds the operation from its children, drawing random parameters from [`lo`, `hi`]
+    /// and noise seeds from `rng`. Rotations draw their angle as a multiple of π,
+    /// scales their factors as powers of two, and images an id between the
+    /// rounded bounds.
+    pub(crate) fn build(
+        self,
+        args: Vec<Operation>,
+        rng: &mut impl Rng,
+        (lo, hi): (f64, f64),
+    ) -> Operation {
+        let mut args = args.into_iter().map(Box::new);
+        let mut arg = || args.next().unwrap();
+        match self {
+            OperationKind::Constant => Operation::Constant(rng.gen_range(lo..=hi)),
+            OperationKind::VarX => Operation::VarX,
+            OperationKind::VarY => Operation::VarY,
+            OperationKind::VarT => Operation::VarT,
+            OperationKind::Circle => {
+                Operation::Circle(rng.gen_range(lo..=hi), rng.gen_range(lo..=hi))
+            }
+            OperationKind::Sum => Operation::Sum(arg(), arg()),
+            OperationKind::Product => Operation::Product(arg(), arg()),
+            OperationKind::Mod => Operation::Mod(arg(), arg()),
+            OperationKind::Sine => Operation::Sine(arg()),
+            OperationKind::Inverse => Operation::Inverse(arg()),
+            OperationKind::PerChannelMask => {
+                Operation::PerChannelMask(arg(), arg(), arg(), rng.gen_range(lo..=hi))
+            }
+            OperationKind::BinaryMask => {
+                Operation::BinaryMask(arg(), arg(), arg(), rng.gen_range(lo..=hi))
+            }
+            OperationKind::SmoothMix => Operation::SmoothMix(arg(), arg(), arg()),
+            OperationKind::Well => Operation::Well(arg()),
+            OperationKind::Tent => Operation::Tent(arg()),
+            OperationKind::RGB => Operation::RGB(arg(), arg(), arg()),
+            OperationKind::Cos => Operation::Cos(arg()),
+            OperationKind::Tan => Operation::Tan(arg()),
+            OperationKind::Atan2 => Operation::Atan2(arg(), arg()),
+            OperationKind::Exp => Operation::Exp(arg()),
+            OperationKind::Log => Operation::Log(arg()),
+            OperationKind::Pow => Operation::Pow(arg(), arg()),
+            OperationKind::Abs => Operation::Abs(arg()),
+            OperationKind::Sqrt => Operation::Sqrt(arg()),
+            OperationKind::Min => Operation::Min(arg(), arg()),
+            OperationKind::Max => Operation::Max(arg(), arg()),
+            OperationKind::Clamp => {
+                let (a, b) = (rng.gen_range(lo..=hi), rng.gen_range(lo..=hi));
+                Operation::Clamp(arg(), a.min(b), a.max(b))
+            }
+            OperationKind::Perlin => Operation::Perlin(arg(), arg(), rng.gen()),
+            OperationKind::Simplex => Operation::Simplex(arg(), arg(), rng.gen()),
+            OperationKind::ValueNoise => Operation::ValueNoise(arg(), arg(), rng.gen()),
+            OperationKind::Worley => Operation::Worley(arg(), arg(), rng.gen()),
+            OperationKind::Fbm => Operation::Fbm(
+                arg(),
+                arg(),
+                rng.gen(),
+                rng.gen_range(1..=noise::MAX_OCTAVES),
+            ),
+            OperationKind::Mandelbrot => Operation::Mandelbrot(arg(), arg()),
+            OperationKind::Julia => {
+                Operation::Julia(arg(), arg(), rng.gen_range(lo..=hi), rng.gen_range(lo..=hi))
+            }
+            OperationKind::Rotate => Operation::Rotate(PI * rng.gen_range(lo..=hi), arg()),
+            OperationKind::Scale => Operation::Scale(
+                rng.gen_range(lo..=hi).exp2(),
+                rng.gen_range(lo..=hi).exp2(),
+                arg(),
+            ),
+            OperationKind::Translate => {
+                Operation::Translate(rng.gen_range(lo..=hi), rng.gen_range(lo..=hi), arg())
+            }
+            OperationKind::Polar => Operation::Polar(arg()),
+            OperationKind::Warp => Operation::Warp(arg(), arg(), arg()),
+            OperationKind::Mirror => Operation::Mirror(arg()),
+            OperationKind::Kaleidoscope => Operation::Kaleidoscope(rng.gen_range(2..=8), arg()),
+            OperationKind::Image => {
+                let (first, last) = (lo.max(0.0).round() as u32, hi.max(0.0).round() as u32);
+                Operation::Image(rng.gen_range(first..=last))
+            }
+        }
+    }
+}
+
+/// Relative weights of the operations a built-in grammar may choose.
+///
+/// Operations with weight 0 are never chosen. At least one leaf (an
+/// operation without children) needs a positive weight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationWeights {
+    weights: [f64; OperationKind::ALL.len()],
+    image_count: u32,
+}
+
+impl OperationWeights {
+    pub fn zero() -> Self {
+        OperationWeights {
+            weights: [0.0; OperationKind::ALL.len()],
+            image_count: 1,
+        }
+    }
+
+    /// Sets the number of input images that image leaves choose from (default 1).
+    pub fn with_image_count(mut self, image_count: u32) -> Self {
+        self.image_count = image_count.max(1);
+        self
+    }
+
+    /// Every operation equally likely.
+    pub fn uniform() -> Self {
+        let mut weights = Self::zero();
+        for kind in OperationKind::ALL {
+            weights.set(kind, 1.0);
+        }
+        weights
+    }
+
+    /// The distribution of `RandomArtGrammar`: every leaf and every original
+    /// internal operation is equally likely.
+    pub fn random_art() -> Self {
+        let mut weights = Self::zero();
+        for kind in [
+            OperationKind::VarX,
+            OperationKind::VarY,
+            OperationKind::VarT,
+            OperationKind::Constant,
+            OperationKind::Circle,
+            OperationKind::Sum,
+            OperationKind::Sine,
+            OperationKind::PerChannelMask,
+            OperationKind::BinaryMask,
+            OperationKind::SmoothMix,
+            OperationKind::Well,
+            OperationKind::Tent,
+            OperationKind::Product,
+            OperationKind::Inverse,
+            OperationKind::Mod,
+        ] {
+            weights.set(kind, 1.0);
+        }
+        weights
+    }
+
+    /// The distribution of `PerrigSongGrammar`'s `C` rule: `A` (split evenly
+    /// between its four leaves) with weight 1, `Sum` and `Product` with weight 2.
+    pub fn perrig_song() -> Self {
+        let mut weights = Self::zero();
+        for kind in [
+            OperationKind::Constant,
+            OperationKind::VarX,
+            OperationKind::VarY,
+            OperationKind::VarT,
+        ] {
+            weights.set(kind, 0.25);
+        }
+        weights.set(OperationKind::Sum, 2.0);
+        weights.set(OperationKind::Product, 2.0);
+        weights
+    }
+
+    pub fn get(&self, kind: OperationKind) -> f64 {
+        self.weights[kind as usize]
+    }
+
+    pub fn set(&mut self, kind: OperationKind, weight: f64) {
+        self.weights[kind as usize] = weight;
+    }
+
+    pub fn has_leaf(&self) -> bool {
+        OperationKind::ALL
+            .iter()
+            .any(|kind| kind.arity() == 0 && self.get(*kind) > 0.0)
+    }
+
+    /// The range passed to [`OperationKind::build`].
+    pub(crate) fn range(&self, kind: OperationKind) -> (f64, f64) {
+        match kind {
+            OperationKind::Image => (0.0, (self.image_count - 1) as f64),
+            _ => kind.default_range(),
+        }
+    }
+
+    /// The operations accepted by `filter` that have a positive weight.
+    pub(crate) fn choices(
+        &self,
+        filter: impl Fn(OperationKind) -> bool,
+    ) -> Vec<WeightedChoice<OperationKind>> {
+        OperationKind::ALL
+            .into_iter()
+            .filter(|kind| filter(*kind) && self.get(*kind) > 0.0)
+            .map(|kind| WeightedChoice::new(kind, self.get(kind)))
+            .collect()
+    }
+}
@@ -0,0 +1,293 @@
+use crate::operations::Operation;
+use crate::vec3::Vec3;
+use image::{ImageResult, RgbImage};
+use std::path::Path;
+
+/// Scaled coordinates are clamped to ±`COORDINATE_LIMIT` texels, keeping
+/// them exact in 32-bit floats and in `i32` range.
+const COORDINATE_LIMIT: f64 = 16_777_216.0;
+
+/// How image leaves sample outside of the [-1, 1]² square covered by the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    /// Tiles the image.
+    #[default]
+    Repeat,
+    /// Extends the edge pixels.
+    Clamp,
+}
+
+/// An image sampled by `Operation::Image`.
+#[derive(Debug, Clone)]
+pub struct InputImage {
+    image: RgbImage,
+    wrap: WrapMode,
+}
+
+impl InputImage {
+    pub fn new(image: RgbImage, wrap: WrapMode) -> Self {
+        InputImage { image, wrap }
+    }
+
+    pub fn open(path: impl AsRef<Path>, wrap: WrapMode) -> ImageResult<Self> {
+        Ok(Self::new(image::open(path)?.to_rgb8(), wrap))
+    }
+
+    pub fn image(&self) -> &RgbImage {
+        &self.image
+    }
+
+    pub fn wrap(&self) -> WrapMode {
+        self.wrap
+    }
+
+    /// Bilinearly interpolates the pixels around (`x`, `y`). The image covers
+    /// [-1, 1]², with its first row at y = -1, and channels are mapped from
+    /// [0, 1] to [-1, 1].
+    pub fn sample(&self, x: f64, y: f64) -> Vec3 {
+        let (width, height) = (self.image.width() as f64, self.image.height() as f64);
+        if width == 0.0 || height == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let u = ((x + 1.0) / 2.0 * width - 0.5).clamp(-COORDINATE_LIMIT, COORDINATE_LIMIT);
+        let v = ((y + 1.0) / 2.0 * height - 0.5).clamp(-COORDINATE_LIMIT, COORDINATE_LIMIT);
+        let (u0, v0) = (u.floor(), v.floor());
+        let (fu, fv) = (u - u0, v - v0);
+
+        let texel = |u: f64, v: f64| {
+            let (u, v) = match self.wrap {
+                WrapMode::Repeat => (u.rem_euclid(width), v.rem_euclid(height)),
+                WrapMode::Clamp => (u.clamp(0.0, width - 1.0), v.clamp(0.0, height - 1.0)),
+            };
+            let pixel = self.image.get_pixel(u as u32, v as u32).0;
+            Vec3::new(
+                pixel[0] as f64 / 255.0 * 2.0 - 1.0,
+                pixel[1] as f64 / 255.0 * 2.0 - 1.0,
+                pixel[2] as f64 / 255.0 * 2.0 - 1.0,
+            )
+        };
+        let mix = |a: Vec3, b: Vec3, t: f64| a + t * (b - a);
+        mix(
+            mix(texel(u0, v0), texel(u0 + 1.0, v0), fu),
+            mix(texel(u0, v0 + 1.0), texel(u0 + 1.0, v0 + 1.0), fu),
+            fv,
+        )
+    }
+}
+
+/// The images available to `Operation::Image` leaves, indexed by id.
+#[derive(Debug, Clone, Default)]
+pub struct ImageSet {
+    images: Vec<InputImage>,
+}
+
+impl ImageSet {
+    pub fn new(images: Vec<InputImage>) -> Self {
+        ImageSet { images }
+    }
+
+    pub fn images(&self) -> &[InputImage] {
+        &self.images
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    /// Samples image `id` at (`x`, `y`); missing images evaluate to 0.
+    pub fn sample(&self, id: u32, x: f64, y: f64) -> Vec3 {
+        match self.images.get(id as usize) {
+            Some(image) => image.sample(x, y),
+            None => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Number of images declared by shaders: every image in the set, and every
+    /// id used by an image leaf of `root`.
+    pub(crate) fn sampled_count(&self, root: &Operation) -> usize {
+        self.images
+            .len()
+            .max(max_image_id(root).map_or(0, |id| id as usize + 1))
+    }
+
+    /// GLSL uniforms `image_<id>` and functions `sample_image_<id>` for every
+    /// image in the set and every image leaf of `root`, mirroring
+    /// [`InputImage::sample`]. Leaves without an image evaluate to 0.
+    pub fn glsl_functions(&self, root: &Operation) -> String {
+        let mut glsl = String::new();
+        for id in 0..self.sampled_count(root) {
+            match self.images.get(id) {
+                Some(image) => glsl.push_str(&glsl_sample_image(
+                    id,
+                    &format!("uniform sampler2D image_{};", id),
+                    &format!("image_{}", id),
+                    &format!(
+                        "vec2({}.0, {}.0)",
+                        image.image.width(),
+                        image.image.height()
+                    ),
+                    image.wrap,
+                    "ivec2(cell)",
+                )),
+                None => glsl.push_str(&glsl_missing_image(id)),
+            }
+        }
+        glsl
+    }
+
+    /// Like [`ImageSet::glsl_functions`], for Shadertoy: image `id` samples
+    /// the texture bound to `iChannel<id>`, so only ids 0 to 3 are available.
+    /// Images of the set only provide the wrap mode.
+    pub fn shadertoy_functions(&self, root: &Operation) -> String {
+        let mut glsl = String::new();
+        for id in 0..self.sampled_count(root) {
+            if id < SHADERTOY_CHANNELS {
+                let wrap = self
+                    .images
+                    .get(id)
+                    .map_or(WrapMode::Repeat, |image| image.wrap);
+                glsl.push_str(&glsl_sample_image(
+                    id,
+                    "",
+                    &format!("iChannel{}", id),
+                    &format!("iChannelResolution[{}].xy", id),
+                    wrap,
+                    // Shadertoy flips textures, so their first row is the bottom one
+                    "ivec2(cell.x, size.y - 1.0 - cell.y)",
+                ));
+            } else {
+                glsl.push_str(&glsl_missing_image(id));
+            }
+        }
+        glsl
+    }
+}
+
+/// Number of input textures of a Shadertoy shader.
+const SHADERTOY_CHANNELS: usize = 4;
+
+fn glsl_sample_image(
+    id: usize,
+    declaration: &str,
+    texture: &str,
+    size: &str,
+    wrap: WrapMode,
+    texel: &str,
+) -> String {
+    GLSL_SAMPLE_IMAGE
+        .replace("#DECLARATION#", declaration)
+        .replace("#TEXTURE#", texture)
+        .replace("#TEXEL#", texel)
+        .replace("#SIZE#", size)
+        .replace(
+            "#WRAP#",
+            match wrap {
+                WrapMode::Repeat => "mod(cell, size)",
+                WrapMode::Clamp => "clamp(cell, vec2(0.0), size - 1.0)",
+            },
+        )
+        .replace("#ID#", &id.to_string())
+}
+
+fn glsl_missing_image(id: usize) -> String {
+    format!("vec3 sample_image_{id}(float x, float y) {{\n    return vec3(0.0);\n}}\n")
+}
+
+fn max_image_id(op: &Operation) -> Option<u32> {
+    let own = match op {
+        Operation::Image(id) => Some(*id),
+        _ => None,
+    };
+    op.children()
+        .into_iter()
+        .filter_map(max_image_id)
+        .chain(own)
+        .max()
+}
+
+// Keep in sync with InputImage::sample
+const GLSL_SAMPLE_IMAGE: &str = r#"
+#DECLARATION#
+
+vec3 image_texel_#ID#(vec2 cell) {
+    vec2 size = #SIZE#;
+    cell = #WRAP#;
+    return texelFetch(#TEXTURE#, #TEXEL#, 0).rgb * 2.0 - 1.0;
+}
+
+vec3 sample_image_#ID#(float x, float y) {
+    vec2 p = clamp((vec2(x, y) + 1.0) / 2.0 * #SIZE# - 0.5, -16777216.0, 16777216.0);
+    vec2 p0 = floor(p);
+    vec2 f = p - p0;
+    vec3 top = mix(image_texel_#ID#(p0), image_texel_#ID#(p0 + vec2(1.0, 0.0)), f.x);
+    vec3 bottom = mix(image_texel_#ID#(p0 + vec2(0.0, 1.0)), image_texel_#ID#(p0 + vec2(1.0, 1.0)), f.x);
+    return mix(top, bottom, f.y);
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    /// Red is -1 in the left column and 1 in the right one, green -1 in the
+    /// first row and 1 in the second, blue always -1.
+    fn image(wrap: WrapMode) -> InputImage {
+        InputImage::new(
+            RgbImage::from_fn(2, 2, |x, y| Rgb([x as u8 * 255, y as u8 * 255, 0])),
+            wrap,
+        )
+    }
+
+    fn sample(image: &InputImage, x: f64, y: f64) -> [f64; 3] {
+        let color = image.sample(x, y);
+        [color.x(), color.y(), color.z()]
+    }
+
+    #[test]
+    fn samples_texel_centers_exactly() {
+        let image = image(WrapMode::Clamp);
+        // The first row covers y from -1 to 0
+        assert_eq!(sample(&image, -0.5, -0.5), [-1.0, -1.0, -1.0]);
+        assert_eq!(sample(&image, 0.5, -0.5), [1.0, -1.0, -1.0]);
+        assert_eq!(sample(&image, -0.5, 0.5), [-1.0, 1.0, -1.0]);
+        assert_eq!(sample(&image, 0.5, 0.5), [1.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn interpolates_between_texels() {
+        let image = image(WrapMode::Clamp);
+        assert_eq!(sample(&image, 0.0, 0.0), [0.0, 0.0, -1.0]);
+        assert_eq!(sample(&image, -0.25, -0.5), [-0.5, -1.0, -1.0]);
+        assert_eq!(sample(&image, 0.5, 0.25), [1.0, 0.5, -1.0]);
+    }
+
+    #[test]
+    fn repeat_wraps_around_the_edges() {
+        let image = image(WrapMode::Repeat);
+        // Halfway between the last texel of one tile and the first of the next
+        assert_eq!(sample(&image, -1.0, -0.5), [0.0, -1.0, -1.0]);
+        assert_eq!(sample(&image, -0.5, 1.0), [-1.0, 0.0, -1.0]);
+        for (x, y) in [(0.25, -0.75), (-0.875, 0.125)] {
+            assert_eq!(sample(&image, x + 2.0, y - 4.0), sample(&image, x, y));
+        }
+    }
+
+    #[test]
+    fn clamp_extends_the_edges() {
+        let image = image(WrapMode::Clamp);
+        assert_eq!(sample(&image, -1.0, -0.5), [-1.0, -1.0, -1.0]);
+        assert_eq!(sample(&image, 3.0, -5.0), [1.0, -1.0, -1.0]);
+        assert_eq!(sample(&image, -7.0, 9.0), [-1.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn empty_images_sample_zero() {
+        let image = InputImage::new(RgbImage::new(0, 0), WrapMode::Repeat);
+        assert_eq!(sample(&image, 0.0, 0.0), [0.0, 0.0, 0.0]);
+    }
+}
@@ -1,5 +1,18 @@
+pub mod batch;
+pub mod compiled;
+pub mod export;
+pub mod fingerprint;
 pub mod grammar;
+pub mod images;
+pub mod noise;
 pub mod operations;
 pub mod renderer;
+pub mod seed;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod shader;
+pub mod simplify;
 pub mod utils;
 pub mod vec3;
+
+pub use fingerprint::{visualize, VisualizeOptions};
@@ -1,12 +1,35 @@
 use clap::Parser;
-use random_art::grammar::{ArtGrammar, PerrigSongGrammar, RandomArtGrammar};
-use random_art::operations::Operation;
+use rand::Rng;
+use random_art::batch::{
+    expand_template, find_duplicate_output, write_manifest, ManifestEntry, ManifestFormat,
+};
+use random_art::export;
+use random_art::fingerprint::{format_sha256_fingerprint, parse_digest, ssh_key_digest};
+use random_art::grammar::{
+    ArtGrammar, OperationKind, OperationWeights, PerrigSongGrammar, RandomArtGrammar,
+};
+#[cfg(feature = "serde")]
+use random_art::grammar::{ConfigurableGrammar, GrammarDefinition};
+use random_art::images::{ImageSet, InputImage, WrapMode};
+use random_art::operations::{parse_expr, Operation};
 use random_art::renderer::*;
+#[cfg(feature = "serde")]
+use random_art::serialization;
+use random_art::shader::{Glsl330, GlslEs300, Hlsl, ShaderBackend, Wgsl};
+use random_art::{visualize, VisualizeOptions};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use std::fs;
-use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::Path;
+use std::process::exit;
 use std::time::SystemTime;
 
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum WrapModeArg {
+    Repeat,
+    Clamp,
+}
+
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
 enum RenderMode {
     File,
@@ -23,11 +46,15 @@ struct Args {
     )]
     seed: Option<String>,
 
+    #[clap(long, help = "Interpret the seed as hex-encoded bytes (e.g. a digest)")]
+    hex_seed: bool,
+
     #[clap(
         short,
         long,
         value_name = "DEPTH",
         default_value = "5",
+        value_parser = parse_depth,
         help = "Depth of the expression tree to generate"
     )]
     depth: usize,
@@ -58,58 +85,899 @@ struct Args {
         help = "Use alternative grammar"
     )]
     use_alternative_grammar: bool,
+
+    #[clap(
+        short,
+        long = "weight",
+        value_name = "OPERATION=WEIGHT",
+        value_parser = parse_weight,
+        help = "Override the weight of an operation in the built-in grammar (repeatable), e.g. cos=2"
+    )]
+    weights: Vec<(OperationKind, f64)>,
+
+    #[clap(
+        short,
+        long,
+        value_name = "EXPRESSION_FILE",
+        help = "Render the expression stored in a file instead of generating one from a seed (a saved tree for .json and .ron files)"
+    )]
+    expression: Option<String>,
+
+    #[clap(
+        long,
+        requires = "expression",
+        help = "Open a window that re-renders the expression file whenever it changes"
+    )]
+    watch: bool,
+
+    #[clap(long, help = "Print the expression tree to stdout")]
+    print_tree: bool,
+
+    #[clap(
+        long,
+        help = "Fold constants and remove redundant operations before printing, saving or rendering the tree"
+    )]
+    simplify: bool,
+
+    #[cfg(feature = "serde")]
+    #[clap(
+        long,
+        value_name = "TREE_FILE",
+        help = "Save the expression tree as JSON or RON (chosen by extension)"
+    )]
+    save_tree: Option<String>,
+
+    #[cfg(feature = "serde")]
+    #[clap(
+        long,
+        value_name = "TREE_FILE",
+        help = "Render a tree saved with --save-tree instead of generating one"
+    )]
+    load_tree: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "WIDTH",
+        default_value = "800",
+        help = "Image width in pixels"
+    )]
+    width: usize,
+
+    #[clap(
+        long,
+        value_name = "HEIGHT",
+        default_value = "800",
+        help = "Image height in pixels"
+    )]
+    height: usize,
+
+    #[clap(
+        long,
+        value_name = "WxH",
+        value_parser = parse_size,
+        conflicts_with_all = ["width", "height"],
+        help = "Image size, e.g. 1920x1080 (overrides --width and --height)"
+    )]
+    size: Option<(usize, usize)>,
+
+    #[cfg(feature = "parallel")]
+    #[clap(
+        long,
+        value_name = "THREADS",
+        help = "Number of render threads (defaults to the number of CPUs)"
+    )]
+    threads: Option<usize>,
+
+    #[cfg(feature = "serde")]
+    #[clap(
+        short,
+        long,
+        value_name = "GRAMMAR_FILE",
+        conflicts_with_all = ["weights", "use_alternative_grammar"],
+        help = "Generate the tree with a grammar loaded from a TOML or RON file"
+    )]
+    grammar_file: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "IMAGE_FILE",
+        help = "Image sampled by image(<id>) leaves, with ids in the order given (repeatable)"
+    )]
+    input_image: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "WRAP_MODE",
+        default_value = "repeat",
+        help = "How input images are sampled outside of [-1, 1]"
+    )]
+    image_wrap: WrapModeArg,
+
+    #[clap(
+        long,
+        help = "Show seed, depth, grammar and tree in window mode (toggle with H, print the command line with C)"
+    )]
+    hud: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-#[macroquad::main("Random Art")]
-async fn main() {
-    let args = Args::parse();
+fn parse_size(size: &str) -> Result<(usize, usize), String> {
+    let (width, height) = size
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got `{}`", size))?;
+    let parse = |value: &str| match value.trim().parse::<usize>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(format!("invalid dimension `{}`", value)),
+    };
+    Ok((parse(width)?, parse(height)?))
+}
 
-    // Create the output directory if it doesn't exist
-    let output_dir = Path::new(&args.output)
+fn parse_depth(depth: &str) -> Result<usize, String> {
+    match depth.trim().parse::<usize>() {
+        Ok(depth) if depth <= PerrigSongGrammar::MAX_DEPTH => Ok(depth),
+        Ok(_) => Err(format!(
+            "depth is above the maximum of {}",
+            PerrigSongGrammar::MAX_DEPTH
+        )),
+        Err(_) => Err(format!("invalid depth `{}`", depth)),
+    }
+}
+
+fn parse_weight(weight: &str) -> Result<(OperationKind, f64), String> {
+    let (name, value) = weight
+        .split_once('=')
+        .ok_or_else(|| format!("expected OPERATION=WEIGHT, got `{}`", weight))?;
+    let kind = OperationKind::from_name(name.trim()).ok_or_else(|| {
+        let known: Vec<&str> = OperationKind::ALL.iter().map(|op| op.name()).collect();
+        format!(
+            "unknown operation `{}` (expected one of {})",
+            name,
+            known.join(", ")
+        )
+    })?;
+    match value.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok((kind, value)),
+        _ => Err(format!("invalid weight `{}`", value)),
+    }
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Render the hash visualization of an SSH public key or a digest
+    Fingerprint(FingerprintArgs),
+    /// Render frames over a range of t as a PNG sequence, GIF or APNG
+    Animate(AnimateArgs),
+    /// Write a self-contained fragment shader rendering the tree
+    Shader(ShaderArgs),
+    /// Write a Shadertoy shader and a standalone WebGL page animating the tree
+    Export(ExportArgs),
+    /// Render many seeds and write a manifest of the rendered images
+    Batch(BatchArgs),
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(group(clap::ArgGroup::new("seeds").required(true).args(["range", "seeds_file", "count"])))]
+struct BatchArgs {
+    #[clap(
+        long,
+        value_name = "START..END",
+        value_parser = parse_seed_range,
+        help = "Render the integer seeds of a range, e.g. 1..100 (END excluded) or 1..=100"
+    )]
+    range: Option<(u64, u64)>,
+
+    #[clap(
+        long,
+        value_name = "SEEDS_FILE",
+        help = "Render the seeds listed in a file, one per line (blank lines and lines starting with # are skipped)"
+    )]
+    seeds_file: Option<String>,
+
+    #[clap(long, value_name = "COUNT", help = "Render this many random seeds")]
+    count: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "generated/{seed}_{depth}.png",
+        help = "Output file name template with {seed}, {depth}, {grammar} and {index} placeholders"
+    )]
+    template: String,
+
+    #[clap(
+        long,
+        value_name = "MANIFEST_FILE",
+        default_value = "generated/manifest.csv",
+        help = "Manifest of seeds, depths, grammars and output files, as CSV or JSON (chosen by extension)"
+    )]
+    manifest: String,
+}
+
+/// Parses `START..END` (END excluded) or `START..=END` into an inclusive range.
+fn parse_seed_range(range: &str) -> Result<(u64, u64), String> {
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| format!("expected START..END, got `{}`", range))?;
+    let (inclusive, end) = match end.strip_prefix('=') {
+        Some(end) => (true, end),
+        None => (false, end),
+    };
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("invalid seed `{}`", value))
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    let end = if inclusive {
+        end
+    } else {
+        end.checked_sub(1)
+            .ok_or_else(|| format!("empty range `{}`", range))?
+    };
+    if start > end {
+        return Err(format!("empty range `{}`", range));
+    }
+    Ok((start, end))
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    #[clap(
+        long,
+        value_name = "SHADER_FILE",
+        default_value = "generated/random_art.glsl",
+        help = "Output file for the Shadertoy shader"
+    )]
+    shadertoy: String,
+
+    #[clap(
+        long,
+        value_name = "HTML_FILE",
+        default_value = "generated/random_art.html",
+        help = "Output file for the HTML page, whose canvas has the image size"
+    )]
+    html: String,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ShaderLanguageArg {
+    Glsl330,
+    GlslEs,
+    Wgsl,
+    Hlsl,
+}
+
+#[derive(clap::Args, Debug)]
+struct ShaderArgs {
+    #[clap(
+        short,
+        long,
+        value_name = "LANGUAGE",
+        default_value = "glsl330",
+        help = "Shading language"
+    )]
+    language: ShaderLanguageArg,
+
+    #[clap(
+        short,
+        long,
+        value_name = "OUTPUT_FILE",
+        help = "Write the shader to a file instead of stdout"
+    )]
+    output: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum AnimationFormatArg {
+    Png,
+    Gif,
+    Apng,
+}
+
+#[derive(clap::Args, Debug)]
+struct AnimateArgs {
+    #[clap(
+        short,
+        long,
+        value_name = "OUTPUT_FILE",
+        default_value = "generated/random_art.gif",
+        help = "Output file; numbered files are written for PNG sequences"
+    )]
+    output: String,
+
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        help = "Output format (defaults to the output file extension: .gif, .apng, or PNG sequence)"
+    )]
+    format: Option<AnimationFormatArg>,
+
+    #[clap(
+        long,
+        value_name = "FRAMES",
+        default_value = "60",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of frames"
+    )]
+    frames: u64,
+
+    #[clap(
+        long,
+        value_name = "FPS",
+        default_value = "30",
+        help = "Frames per second"
+    )]
+    fps: u16,
+
+    #[clap(
+        long,
+        value_name = "T",
+        default_value = "-1.0",
+        allow_negative_numbers = true,
+        help = "Value of t in the first frame"
+    )]
+    t_start: f64,
+
+    #[clap(
+        long,
+        value_name = "T",
+        default_value = "1.0",
+        allow_negative_numbers = true,
+        help = "Value of t in the last frame"
+    )]
+    t_end: f64,
+}
+
+#[derive(clap::Args, Debug)]
+struct FingerprintArgs {
+    #[clap(
+        value_name = "KEY_OR_DIGEST",
+        help = "SSH public key file, or a digest as hex or SHA256:<base64>"
+    )]
+    input: String,
+
+    #[clap(
+        short,
+        long,
+        value_name = "OUTPUT_FILE",
+        default_value = "generated/fingerprint.png",
+        help = "Sets the output file name"
+    )]
+    output: String,
+
+    #[clap(
+        short,
+        long,
+        value_name = "DEPTH",
+        default_value = "5",
+        value_parser = parse_depth,
+        help = "Depth of the expression tree to generate"
+    )]
+    depth: usize,
+
+    #[clap(
+        long,
+        value_name = "PIXELS",
+        default_value = "256",
+        help = "Width and height of the image"
+    )]
+    size: usize,
+}
+
+/// Generates trees with the grammar options given on the command line.
+struct TreeGenerator {
+    weights: Vec<(OperationKind, f64)>,
+    image_count: u32,
+    #[cfg(feature = "serde")]
+    grammar: Option<GrammarDefinition>,
+}
+
+impl TreeGenerator {
+    fn generate(&self, params: &TreeParams) -> Result<Operation, String> {
+        let seed = params
+            .seed()
+            .map_err(|err| format!("Invalid hex seed: {}", err))?;
+        if params.depth > PerrigSongGrammar::MAX_DEPTH {
+            return Err(format!(
+                "Depth {} is above the maximum of {}",
+                params.depth,
+                PerrigSongGrammar::MAX_DEPTH
+            ));
+        }
+        #[cfg(feature = "serde")]
+        if let Some(definition) = &self.grammar {
+            return Ok(
+                ConfigurableGrammar::new(definition.clone(), &seed).generate_tree(params.depth)
+            );
+        }
+        if self.weights.is_empty() {
+            return Ok(if params.alternative_grammar {
+                RandomArtGrammar::from_seed(&seed).generate_tree(params.depth)
+            } else {
+                PerrigSongGrammar::from_seed(&seed).generate_tree(params.depth)
+            });
+        }
+        let mut weights = if params.alternative_grammar {
+            OperationWeights::random_art()
+        } else {
+            OperationWeights::perrig_song()
+        };
+        for &(kind, weight) in &self.weights {
+            weights.set(kind, weight);
+        }
+        let weights = weights.with_image_count(self.image_count);
+        if !weights.has_leaf() {
+            return Err(
+                "At least one of x, y, t, constant, circle or image needs a positive weight"
+                    .to_string(),
+            );
+        }
+        Ok(if params.alternative_grammar {
+            RandomArtGrammar::from_seed(&seed)
+                .with_weights(weights)
+                .generate_tree(params.depth)
+        } else {
+            PerrigSongGrammar::from_seed(&seed)
+                .with_weights(weights)
+                .generate_tree(params.depth)
+        })
+    }
+}
+
+/// Loads an expression file: a saved tree for `.json` and `.ron` files, the
+/// printed syntax otherwise.
+fn load_expression(path: &Path) -> Result<Operation, String> {
+    let saved_tree = matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("json" | "ron")
+    );
+    if saved_tree {
+        #[cfg(feature = "serde")]
+        return serialization::load_tree(path)
+            .map_err(|err| format!("{}: {}", path.display(), err));
+        #[cfg(not(feature = "serde"))]
+        return Err(format!(
+            "{}: loading saved trees requires building with --features serde",
+            path.display()
+        ));
+    }
+    let source = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    parse_expr(&source).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Name of the grammar trees are generated with, for batch manifests.
+fn grammar_name(args: &Args) -> String {
+    #[cfg(feature = "serde")]
+    if let Some(path) = &args.grammar_file {
+        return path.clone();
+    }
+    if args.use_alternative_grammar {
+        "random_art".to_string()
+    } else {
+        "perrig_song".to_string()
+    }
+}
+
+fn batch_seeds(batch_args: &BatchArgs) -> Vec<String> {
+    if let Some((start, end)) = batch_args.range {
+        (start..=end).map(|seed| seed.to_string()).collect()
+    } else if let Some(path) = &batch_args.seeds_file {
+        let seeds = fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            exit(1);
+        });
+        seeds
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()
+    } else {
+        let mut rng = rand::thread_rng();
+        (0..batch_args.count.unwrap_or_default())
+            .map(|_| format!("{:016x}", rng.gen::<u64>()))
+            .collect()
+    }
+}
+
+fn batch(
+    args: &Args,
+    batch_args: &BatchArgs,
+    generator: &TreeGenerator,
+    images: &ImageSet,
+    x_res: usize,
+    y_res: usize,
+) {
+    if args.hex_seed && batch_args.range.is_some() {
+        // Decimal seeds are not hex digests, and odd lengths do not even parse
+        eprintln!("--range cannot be used with --hex-seed; list hex seeds with --seeds-file");
+        exit(1);
+    }
+    let grammar = grammar_name(args);
+    let seeds = batch_seeds(batch_args);
+    let outputs: Vec<String> = seeds
+        .iter()
+        .enumerate()
+        .map(|(index, seed)| {
+            expand_template(&batch_args.template, seed, args.depth, &grammar, index)
+        })
+        .collect();
+    if let Some((first, second)) = find_duplicate_output(&outputs) {
+        eprintln!(
+            "Seeds {:?} and {:?} would both be written to {}; add {{index}} to the template",
+            seeds[first], seeds[second], outputs[first]
+        );
+        exit(1);
+    }
+    println!("Rendering {} images", seeds.len());
+    let render = |(seed, output): (&String, &String)| -> Result<ManifestEntry, String> {
+        let params = TreeParams {
+            seed: seed.clone(),
+            hex_seed: args.hex_seed,
+            depth: args.depth,
+            alternative_grammar: args.use_alternative_grammar,
+        };
+        let mut root = generator
+            .generate(&params)
+            .map_err(|err| format!("{}: {}", seed, err))?;
+        if args.simplify {
+            root = root.simplify();
+        }
+        let output = output.clone();
+        create_output_dir(&output);
+        FileRenderer::new(output.clone())
+            .with_images(images.clone())
+            .render(x_res, y_res, &root)
+            .map_err(|err| format!("{}: {}", output, err))?;
+        println!("{}", output);
+        Ok(ManifestEntry {
+            seed: seed.clone(),
+            depth: args.depth,
+            grammar: grammar.clone(),
+            output,
+        })
+    };
+    #[cfg(feature = "parallel")]
+    let results: Vec<_> = seeds.par_iter().zip(&outputs).map(render).collect();
+    #[cfg(not(feature = "parallel"))]
+    let results: Vec<_> = seeds.iter().zip(&outputs).map(render).collect();
+
+    let mut entries = Vec::new();
+    let mut failed = false;
+    for result in results {
+        match result {
+            Ok(entry) => entries.push(entry),
+            Err(err) => {
+                eprintln!("{}", err);
+                failed = true;
+            }
+        }
+    }
+    let format = ManifestFormat::from_path(&batch_args.manifest);
+    write_manifest(&batch_args.manifest, format, &entries).unwrap_or_else(|err| {
+        eprintln!("{}: {}", batch_args.manifest, err);
+        exit(1);
+    });
+    if failed {
+        exit(1);
+    }
+}
+
+/// Quotes `arg` for POSIX shells if it contains anything besides common
+/// path and option characters.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// The options reproducing the rendered tree and image size, except for the
+/// seed, depth and grammar, which the window can change.
+fn reproduction_args(args: &Args, x_res: usize, y_res: usize) -> Vec<String> {
+    let mut options = Vec::new();
+    if let Some(path) = &args.expression {
+        options.extend(["--expression".to_string(), path.clone()]);
+    }
+    #[cfg(feature = "serde")]
+    {
+        if let Some(path) = &args.load_tree {
+            options.extend(["--load-tree".to_string(), path.clone()]);
+        }
+        if let Some(path) = &args.grammar_file {
+            options.extend(["--grammar-file".to_string(), path.clone()]);
+        }
+    }
+    for (kind, weight) in &args.weights {
+        options.extend([
+            "--weight".to_string(),
+            format!("{}={}", kind.name(), weight),
+        ]);
+    }
+    for path in &args.input_image {
+        options.extend(["--input-image".to_string(), path.clone()]);
+    }
+    if let WrapModeArg::Clamp = args.image_wrap {
+        options.extend(["--image-wrap".to_string(), "clamp".to_string()]);
+    }
+    if args.simplify {
+        options.push("--simplify".to_string());
+    }
+    options.extend(["--size".to_string(), format!("{}x{}", x_res, y_res)]);
+    options
+}
+
+/// The command line rendering the tree generated from `params`, or the tree
+/// given by `options` if there are no `params`.
+fn command_line(options: &[String], params: Option<&TreeParams>) -> String {
+    let mut command = vec![std::env::args().next().unwrap_or("random-art".to_string())];
+    if let Some(params) = params {
+        command.extend(["--seed".to_string(), params.seed.clone()]);
+        if params.hex_seed {
+            command.push("--hex-seed".to_string());
+        }
+        command.extend(["--depth".to_string(), params.depth.to_string()]);
+        if params.alternative_grammar {
+            command.push("--use-alternative-grammar".to_string());
+        }
+    }
+    command.extend(options.iter().cloned());
+    command
+        .iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn create_output_dir(output: &str) {
+    let output_dir = Path::new(output)
         .parent()
         .expect("Failed to get output directory");
     if !output_dir.exists() {
         fs::create_dir_all(output_dir).expect("Failed to create output directory");
     }
+}
+
+fn fingerprint(args: &FingerprintArgs) {
+    let digest = match fs::read_to_string(&args.input) {
+        Ok(contents) => ssh_key_digest(&contents),
+        Err(_) => parse_digest(&args.input),
+    };
+    let digest = digest.unwrap_or_else(|err| {
+        eprintln!("{}: {}", args.input, err);
+        exit(1);
+    });
+    println!("{}", format_sha256_fingerprint(&digest));
+
+    create_output_dir(&args.output);
+    let opts = VisualizeOptions {
+        width: args.size,
+        height: args.size,
+        depth: args.depth,
+    };
+    visualize(&digest, &opts)
+        .save(&args.output)
+        .expect("Failed to write image");
+}
+
+fn main() {
+    let args = Args::parse();
+
+    #[cfg(feature = "parallel")]
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Failed to set up render threads");
+    }
 
-    let x_res = 800;
-    let y_res = 800;
+    if let Some(Command::Fingerprint(fingerprint_args)) = &args.command {
+        fingerprint(fingerprint_args);
+        return;
+    }
 
-    let seed = match args.seed {
-        Some(seed_str) => {
-            let mut hasher = DefaultHasher::new();
-            seed_str.hash(&mut hasher);
-            hasher.finish()
+    // Create the output directory if it doesn't exist
+    match &args.command {
+        Some(Command::Animate(animate_args)) => create_output_dir(&animate_args.output),
+        Some(Command::Export(export_args)) => {
+            create_output_dir(&export_args.shadertoy);
+            create_output_dir(&export_args.html);
         }
+        Some(Command::Batch(batch_args)) => create_output_dir(&batch_args.manifest),
+        Some(Command::Shader(shader_args)) => {
+            if let Some(output) = &shader_args.output {
+                create_output_dir(output);
+            }
+        }
+        _ => create_output_dir(&args.output),
+    }
+
+    let (x_res, y_res) = args.size.unwrap_or((args.width, args.height));
+
+    let wrap = match args.image_wrap {
+        WrapModeArg::Repeat => WrapMode::Repeat,
+        WrapModeArg::Clamp => WrapMode::Clamp,
+    };
+    let images = ImageSet::new(
+        args.input_image
+            .iter()
+            .map(|path| {
+                InputImage::open(path, wrap).unwrap_or_else(|err| {
+                    eprintln!("{}: {}", path, err);
+                    exit(1);
+                })
+            })
+            .collect(),
+    );
+
+    #[cfg(feature = "serde")]
+    let loaded_tree = args.load_tree.as_ref().map(|path| {
+        serialization::load_tree(path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            exit(1);
+        })
+    });
+    #[cfg(not(feature = "serde"))]
+    let loaded_tree: Option<Operation> = None;
+
+    let generator = TreeGenerator {
+        weights: args.weights.clone(),
+        image_count: images.len() as u32,
+        #[cfg(feature = "serde")]
+        grammar: args.grammar_file.as_ref().map(|path| {
+            GrammarDefinition::load(path).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path, err);
+                exit(1);
+            })
+        }),
+    };
+
+    if let Some(Command::Batch(batch_args)) = &args.command {
+        batch(&args, batch_args, &generator, &images, x_res, y_res);
+        return;
+    }
+
+    let seed_str = match &args.seed {
+        Some(seed_str) => seed_str.clone(),
         None => {
             let now = SystemTime::now();
             let since_epoch = now
                 .duration_since(std::time::UNIX_EPOCH)
                 .expect("Time went backwards");
-            let mut hasher = DefaultHasher::new();
-            since_epoch.as_millis().hash(&mut hasher);
-            println!(
+            eprintln!(
                 "No seed provided, using current time as seed: {}",
                 since_epoch.as_millis()
             );
-
-            hasher.finish()
+            since_epoch.as_millis().to_string()
         }
     };
+    let params = TreeParams {
+        seed: seed_str,
+        hex_seed: args.hex_seed,
+        depth: args.depth,
+        alternative_grammar: args.use_alternative_grammar,
+    };
+    if let Err(err) = params.seed() {
+        eprintln!("Invalid hex seed: {}", err);
+        exit(1);
+    }
 
-    let root: Operation;
-    if args.use_alternative_grammar {
-        root = RandomArtGrammar::new(seed).generate_tree(args.depth);
+    let generated = loaded_tree.is_none() && args.expression.is_none();
+    let mut root = if let Some(tree) = loaded_tree {
+        tree
+    } else if let Some(path) = &args.expression {
+        load_expression(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(1);
+        })
     } else {
-        root = PerrigSongGrammar::new(seed).generate_tree(args.depth);
+        generator.generate(&params).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(1);
+        })
+    };
+
+    if args.simplify {
+        root = root.simplify();
     }
 
-    if let RenderMode::Window = args.render_mode {
+    if args.print_tree {
+        println!("{}", root);
+    }
+
+    #[cfg(feature = "serde")]
+    if let Some(path) = &args.save_tree {
+        if let Err(err) = serialization::save_tree(path, &root) {
+            eprintln!("{}: {}", path, err);
+            exit(1);
+        }
+    }
+
+    if let Some(Command::Shader(shader_args)) = &args.command {
+        let backend: &dyn ShaderBackend = match shader_args.language {
+            ShaderLanguageArg::Glsl330 => &Glsl330,
+            ShaderLanguageArg::GlslEs => &GlslEs300,
+            ShaderLanguageArg::Wgsl => &Wgsl,
+            ShaderLanguageArg::Hlsl => &Hlsl,
+        };
+        let shader = backend.fragment_shader(&root, &RenderSpec::default(), &images);
+        match &shader_args.output {
+            Some(path) => fs::write(path, shader).expect("Failed to write shader"),
+            None => print!("{}", shader),
+        }
+    } else if let Some(Command::Export(export_args)) = &args.command {
+        let spec = RenderSpec::default();
+        fs::write(
+            &export_args.shadertoy,
+            export::shadertoy_shader(&root, &spec, &images),
+        )
+        .expect("Failed to write Shadertoy shader");
+        let page = export::html_page(&root, &spec, &images, x_res, y_res)
+            .expect("Failed to encode input images");
+        fs::write(&export_args.html, page).expect("Failed to write HTML page");
+        println!("Wrote {} and {}", export_args.shadertoy, export_args.html);
+    } else if let Some(Command::Animate(animate_args)) = &args.command {
+        let format = match animate_args.format {
+            Some(AnimationFormatArg::Png) => AnimationFormat::PngSequence,
+            Some(AnimationFormatArg::Gif) => AnimationFormat::Gif,
+            Some(AnimationFormatArg::Apng) => AnimationFormat::Apng,
+            None => AnimationFormat::from_path(&animate_args.output),
+        };
+        let animation = Animation {
+            frames: animate_args.frames as usize,
+            fps: animate_args.fps,
+            t_start: animate_args.t_start,
+            t_end: animate_args.t_end,
+            format,
+        };
+        println!("Rendering {} frames", animation.frames);
+        FileRenderer::new(animate_args.output.clone())
+            .with_images(images)
+            .render_animation(x_res, y_res, &root, &animation)
+            .expect("Failed to render animation");
+    } else if args.watch || matches!(args.render_mode, RenderMode::Window) {
         println!("Rendering to window");
-        WindowRenderer::new().render(x_res, y_res, &root).await;
+        // Only window mode opens a window, so the other modes also work headless.
+        let conf = macroquad::window::Conf {
+            window_title: "Random Art".to_string(),
+            window_width: x_res as i32,
+            window_height: y_res as i32,
+            ..Default::default()
+        };
+        let options = reproduction_args(&args, x_res, y_res);
+        let mut renderer = WindowRenderer::new()
+            .with_images(images)
+            .with_output(args.output)
+            .with_hud(args.hud)
+            .with_command_line(move |params| command_line(&options, params));
+        let simplify = args.simplify;
+        if let (true, Some(path)) = (args.watch, &args.expression) {
+            renderer = renderer.with_watch(path, move |path| {
+                let tree = load_expression(path)?;
+                Ok(if simplify { tree.simplify() } else { tree })
+            });
+        }
+        if generated {
+            renderer = renderer.with_generator(params, move |params| {
+                let tree = generator.generate(params)?;
+                Ok(if simplify { tree.simplify() } else { tree })
+            });
+        }
+        macroquad::Window::from_config(conf, async move {
+            if let Err(err) = renderer.render(&root).await {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        });
     } else {
         println!("Rendering to file");
         FileRenderer::new(args.output)
+            .with_images(images)
             .render(x_res, y_res, &root)
             .expect("Failed to render image");
     }
@@ -0,0 +1,295 @@
+//! Procedural noise used by the noise operations.
+//!
+//! Lattice points are hashed with 32-bit integer arithmetic, which GLSL 330
+//! supports through `uint`, so the CPU renderers and the shaders pick the same
+//! gradients and feature points for the same seed. [`GLSL_FUNCTIONS`] mirrors
+//! every function of this module; results agree up to the precision of
+//! 32-bit floats in the shader.
+
+/// Noise inputs are multiplied by this, so the plane spans several cells.
+pub const NOISE_SCALE: f64 = 4.0;
+/// Scaled inputs are clamped to ±`NOISE_LIMIT`, keeping cell indices in `i32` range.
+pub const NOISE_LIMIT: f64 = 4096.0;
+/// Largest number of octaves summed by [`fbm`].
+pub const MAX_OCTAVES: u32 = 8;
+
+const GRADIENTS: [(f64, f64); 8] = [
+    (1.0, 1.0),
+    (-1.0, 1.0),
+    (1.0, -1.0),
+    (-1.0, -1.0),
+    (1.0, 0.0),
+    (-1.0, 0.0),
+    (0.0, 1.0),
+    (0.0, -1.0),
+];
+
+/// Integer hash with good avalanche behaviour ("lowbias32" by Chris Wellons).
+fn hash(mut h: u32) -> u32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    h
+}
+
+fn hash_cell(ix: i32, iy: i32, seed: u32) -> u32 {
+    hash(ix as u32 ^ hash(iy as u32 ^ hash(seed)))
+}
+
+/// Maps a hash to [0, 1).
+fn unit(h: u32) -> f64 {
+    h as f64 / 4294967296.0
+}
+
+fn prepare(v: f64) -> f64 {
+    (v * NOISE_SCALE).clamp(-NOISE_LIMIT, NOISE_LIMIT)
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn gradient(h: u32, dx: f64, dy: f64) -> f64 {
+    let (gx, gy) = GRADIENTS[(h & 7) as usize];
+    gx * dx + gy * dy
+}
+
+/// Interpolates `corner(i, j)` over the cell containing (`x`, `y`).
+fn interpolate(x: f64, y: f64, corner: impl Fn(i32, i32, f64, f64) -> f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let n00 = corner(ix, iy, fx, fy);
+    let n10 = corner(ix + 1, iy, fx - 1.0, fy);
+    let n01 = corner(ix, iy + 1, fx, fy - 1.0);
+    let n11 = corner(ix + 1, iy + 1, fx - 1.0, fy - 1.0);
+    let (u, v) = (fade(fx), fade(fy));
+    lerp(lerp(n00, n10, u), lerp(n01, n11, u), v)
+}
+
+/// Gradient (Perlin) noise, roughly in [-1, 1].
+pub fn perlin(x: f64, y: f64, seed: u32) -> f64 {
+    interpolate(prepare(x), prepare(y), |ix, iy, dx, dy| {
+        gradient(hash_cell(ix, iy, seed), dx, dy)
+    })
+}
+
+/// Value noise in [-1, 1].
+pub fn value(x: f64, y: f64, seed: u32) -> f64 {
+    interpolate(prepare(x), prepare(y), |ix, iy, _, _| {
+        2.0 * unit(hash_cell(ix, iy, seed)) - 1.0
+    })
+}
+
+/// Simplex noise, roughly in [-1, 1].
+pub fn simplex(x: f64, y: f64, seed: u32) -> f64 {
+    const F2: f64 = 0.366_025_403_784_438_6; // (sqrt(3) - 1) / 2
+    const G2: f64 = 0.211_324_865_405_187_1; // (3 - sqrt(3)) / 6
+    let (x, y) = (prepare(x), prepare(y));
+
+    let s = (x + y) * F2;
+    let (i, j) = ((x + s).floor(), (y + s).floor());
+    let t = (i + j) * G2;
+    let (x0, y0) = (x - (i - t), y - (j - t));
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+    let (ix, iy) = (i as i32, j as i32);
+
+    let corner = |ci: i32, cj: i32, dx: f64, dy: f64| {
+        let falloff = 0.5 - dx * dx - dy * dy;
+        if falloff < 0.0 {
+            0.0
+        } else {
+            let falloff = falloff * falloff;
+            falloff * falloff * gradient(hash_cell(ix + ci, iy + cj, seed), dx, dy)
+        }
+    };
+    let n0 = corner(0, 0, x0, y0);
+    let n1 = corner(i1, j1, x0 - i1 as f64 + G2, y0 - j1 as f64 + G2);
+    let n2 = corner(1, 1, x0 - 1.0 + 2.0 * G2, y0 - 1.0 + 2.0 * G2);
+    70.0 * (n0 + n1 + n2)
+}
+
+/// Cellular (Worley) noise: the distance to the closest feature point, with
+/// one point per cell, mapped from [0, 1] to [-1, 1].
+pub fn worley(x: f64, y: f64, seed: u32) -> f64 {
+    let (x, y) = (prepare(x), prepare(y));
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i32, y0 as i32);
+
+    let mut closest: f64 = 1.0;
+    for j in -1..=1 {
+        for i in -1..=1 {
+            let h = hash_cell(ix + i, iy + j, seed);
+            let dx = i as f64 + unit(h) - fx;
+            let dy = j as f64 + unit(hash(h)) - fy;
+            closest = closest.min((dx * dx + dy * dy).sqrt());
+        }
+    }
+    2.0 * closest - 1.0
+}
+
+/// Fractional Brownian motion: `octaves` layers of gradient noise, each with
+/// twice the frequency and half the amplitude of the previous one, divided by
+/// the total amplitude. The number of octaves is clamped to [1, `MAX_OCTAVES`].
+pub fn fbm(x: f64, y: f64, seed: u32, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for octave in 0..octaves.clamp(1, MAX_OCTAVES) {
+        sum += amplitude * perlin(x * frequency, y * frequency, seed.wrapping_add(octave));
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+/// GLSL 330 versions of the noise functions, with `vec3` overloads that
+/// apply them per channel.
+pub const GLSL_FUNCTIONS: &str = r#"
+// Keep in sync with noise.rs
+const float NOISE_SCALE = 4.0;
+const float NOISE_LIMIT = 4096.0;
+const uint MAX_OCTAVES = 8u;
+
+const vec2 GRADIENTS[8] = vec2[8](
+    vec2(1.0, 1.0), vec2(-1.0, 1.0), vec2(1.0, -1.0), vec2(-1.0, -1.0),
+    vec2(1.0, 0.0), vec2(-1.0, 0.0), vec2(0.0, 1.0), vec2(0.0, -1.0)
+);
+
+uint noise_hash(uint h) {
+    h ^= h >> 16;
+    h *= 0x7feb352du;
+    h ^= h >> 15;
+    h *= 0x846ca68bu;
+    h ^= h >> 16;
+    return h;
+}
+
+uint hash_cell(ivec2 cell, uint seed) {
+    return noise_hash(uint(cell.x) ^ noise_hash(uint(cell.y) ^ noise_hash(seed)));
+}
+
+float noise_unit(uint h) {
+    return float(h) / 4294967296.0;
+}
+
+vec2 noise_prepare(float x, float y) {
+    return clamp(vec2(x, y) * NOISE_SCALE, -NOISE_LIMIT, NOISE_LIMIT);
+}
+
+vec2 noise_fade(vec2 t) {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+float noise_gradient(uint h, vec2 d) {
+    return dot(GRADIENTS[h & 7u], d);
+}
+
+float perlin_fn(float x, float y, uint seed) {
+    vec2 p = noise_prepare(x, y);
+    vec2 p0 = floor(p);
+    vec2 f = p - p0;
+    ivec2 i = ivec2(p0);
+    float n00 = noise_gradient(hash_cell(i, seed), f);
+    float n10 = noise_gradient(hash_cell(i + ivec2(1, 0), seed), f - vec2(1.0, 0.0));
+    float n01 = noise_gradient(hash_cell(i + ivec2(0, 1), seed), f - vec2(0.0, 1.0));
+    float n11 = noise_gradient(hash_cell(i + ivec2(1, 1), seed), f - vec2(1.0, 1.0));
+    vec2 u = noise_fade(f);
+    return mix(mix(n00, n10, u.x), mix(n01, n11, u.x), u.y);
+}
+
+float value_noise_fn(float x, float y, uint seed) {
+    vec2 p = noise_prepare(x, y);
+    vec2 p0 = floor(p);
+    vec2 f = p - p0;
+    ivec2 i = ivec2(p0);
+    float n00 = 2.0 * noise_unit(hash_cell(i, seed)) - 1.0;
+    float n10 = 2.0 * noise_unit(hash_cell(i + ivec2(1, 0), seed)) - 1.0;
+    float n01 = 2.0 * noise_unit(hash_cell(i + ivec2(0, 1), seed)) - 1.0;
+    float n11 = 2.0 * noise_unit(hash_cell(i + ivec2(1, 1), seed)) - 1.0;
+    vec2 u = noise_fade(f);
+    return mix(mix(n00, n10, u.x), mix(n01, n11, u.x), u.y);
+}
+
+float simplex_corner(ivec2 cell, uint seed, vec2 d) {
+    float falloff = 0.5 - dot(d, d);
+    if (falloff < 0.0) {
+        return 0.0;
+    }
+    falloff *= falloff;
+    return falloff * falloff * noise_gradient(hash_cell(cell, seed), d);
+}
+
+float simplex_fn(float x, float y, uint seed) {
+    const float F2 = 0.3660254037844386;
+    const float G2 = 0.2113248654051871;
+    vec2 p = noise_prepare(x, y);
+    vec2 ij = floor(p + (p.x + p.y) * F2);
+    vec2 d0 = p - (ij - (ij.x + ij.y) * G2);
+    ivec2 o = d0.x > d0.y ? ivec2(1, 0) : ivec2(0, 1);
+    ivec2 i = ivec2(ij);
+    float n0 = simplex_corner(i, seed, d0);
+    float n1 = simplex_corner(i + o, seed, d0 - vec2(o) + G2);
+    float n2 = simplex_corner(i + ivec2(1, 1), seed, d0 - 1.0 + 2.0 * G2);
+    return 70.0 * (n0 + n1 + n2);
+}
+
+float worley_fn(float x, float y, uint seed) {
+    vec2 p = noise_prepare(x, y);
+    vec2 p0 = floor(p);
+    vec2 f = p - p0;
+    ivec2 i = ivec2(p0);
+    float closest = 1.0;
+    for (int j = -1; j <= 1; j++) {
+        for (int k = -1; k <= 1; k++) {
+            uint h = hash_cell(i + ivec2(k, j), seed);
+            vec2 d = vec2(k, j) + vec2(noise_unit(h), noise_unit(noise_hash(h))) - f;
+            closest = min(closest, sqrt(dot(d, d)));
+        }
+    }
+    return 2.0 * closest - 1.0;
+}
+
+float fbm_fn(float x, float y, uint seed, uint octaves) {
+    float sum = 0.0;
+    float total = 0.0;
+    float amplitude = 1.0;
+    float frequency = 1.0;
+    for (uint octave = 0u; octave < clamp(octaves, 1u, MAX_OCTAVES); octave++) {
+        sum += amplitude * perlin_fn(x * frequency, y * frequency, seed + octave);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return sum / total;
+}
+
+vec3 perlin_fn(vec3 x, vec3 y, uint seed) {
+    return vec3(perlin_fn(x.x, y.x, seed), perlin_fn(x.y, y.y, seed), perlin_fn(x.z, y.z, seed));
+}
+
+vec3 value_noise_fn(vec3 x, vec3 y, uint seed) {
+    return vec3(value_noise_fn(x.x, y.x, seed), value_noise_fn(x.y, y.y, seed), value_noise_fn(x.z, y.z, seed));
+}
+
+vec3 simplex_fn(vec3 x, vec3 y, uint seed) {
+    return vec3(simplex_fn(x.x, y.x, seed), simplex_fn(x.y, y.y, seed), simplex_fn(x.z, y.z, seed));
+}
+
+vec3 worley_fn(vec3 x, vec3 y, uint seed) {
+    return vec3(worley_fn(x.x, y.x, seed), worley_fn(x.y, y.y, seed), worley_fn(x.z, y.z, seed));
+}
+
+vec3 fbm_fn(vec3 x, vec3 y, uint seed, uint octaves) {
+    return vec3(fbm_fn(x.x, y.x, seed, octaves), fbm_fn(x.y, y.y, seed, octaves), fbm_fn(x.z, y.z, seed, octaves));
+}
+"#;
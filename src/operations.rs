@@ -1,7 +1,18 @@
+use crate::images::ImageSet;
+use crate::noise;
+use crate::shader::{tree_functions, Glsl330};
 use crate::vec3::Vec3;
 use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+use thiserror::Error;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum Operation {
     Sum(Box<Operation>, Box<Operation>),
     Product(Box<Operation>, Box<Operation>),
@@ -18,17 +29,87 @@ pub enum Operation {
     SmoothMix(Box<Operation>, Box<Operation>, Box<Operation>),
     Well(Box<Operation>),
     Tent(Box<Operation>),
+    #[cfg_attr(feature = "serde", serde(rename = "rgb"))]
     RGB(Box<Operation>, Box<Operation>, Box<Operation>),
+    Cos(Box<Operation>),
+    /// Tangent, clamped to ±`TAN_LIMIT` so poles stay finite.
+    Tan(Box<Operation>),
+    /// `atan2(a, b) / π` per channel, in [-1, 1].
+    Atan2(Box<Operation>, Box<Operation>),
+    /// Exponential with the input capped at `EXP_LIMIT`.
+    Exp(Box<Operation>),
+    /// Natural logarithm of `max(|a|, EPSILON)`.
+    Log(Box<Operation>),
+    /// `max(|a|, EPSILON)` raised to `b`.
+    Pow(Box<Operation>, Box<Operation>),
+    Abs(Box<Operation>),
+    /// Square root of `|a|`.
+    Sqrt(Box<Operation>),
+    Min(Box<Operation>, Box<Operation>),
+    Max(Box<Operation>, Box<Operation>),
+    /// Clamps `a` to [low, high].
+    Clamp(Box<Operation>, f64, f64),
+    /// Gradient noise at (a, b) per channel, with a seed.
+    Perlin(Box<Operation>, Box<Operation>, u32),
+    /// Simplex noise at (a, b) per channel, with a seed.
+    Simplex(Box<Operation>, Box<Operation>, u32),
+    /// Value noise at (a, b) per channel, with a seed.
+    ValueNoise(Box<Operation>, Box<Operation>, u32),
+    /// Distance to the closest cellular feature point at (a, b) per channel, with a seed.
+    Worley(Box<Operation>, Box<Operation>, u32),
+    /// Fractional Brownian motion at (a, b) per channel, with a seed and an octave count.
+    Fbm(Box<Operation>, Box<Operation>, u32, u32),
+    /// Smooth escape time of the Mandelbrot set at c = a + bi per channel, in [-1, 1].
+    Mandelbrot(Box<Operation>, Box<Operation>),
+    /// Smooth escape time of the Julia set for the constant c = re + im·i, at
+    /// z = a + bi per channel, in [-1, 1].
+    Julia(Box<Operation>, Box<Operation>, f64, f64),
+    /// Evaluates the child with the plane rotated by an angle in radians.
+    Rotate(f64, Box<Operation>),
+    /// Evaluates the child at (x * sx, y * sy).
+    Scale(f64, f64, Box<Operation>),
+    /// Evaluates the child at (x - dx, y - dy), moving it by (dx, dy).
+    Translate(f64, f64, Box<Operation>),
+    /// Evaluates the child at polar coordinates (r, θ / π).
+    Polar(Box<Operation>),
+    /// Evaluates the third child at (x + dx, y + dy), where dx and dy are the
+    /// channel means of the first two children.
+    Warp(Box<Operation>, Box<Operation>, Box<Operation>),
+    /// Evaluates the child at (|x|, y).
+    Mirror(Box<Operation>),
+    /// Evaluates the child with the plane folded into `n` mirrored wedges
+    /// around the origin.
+    Kaleidoscope(u32, Box<Operation>),
+    /// Samples input image `id`, which covers the square [-1, 1]², with
+    /// channels mapped from [0, 1] to [-1, 1].
+    Image(u32),
 }
 
+/// Largest magnitude returned by `Operation::Tan`.
+pub const TAN_LIMIT: f64 = 10.0;
+/// Largest input of `Operation::Exp`, keeping results finite in 32-bit shaders too.
+pub const EXP_LIMIT: f64 = 10.0;
+/// Smallest magnitude passed to the logarithm and power functions.
+pub const EPSILON: f64 = 1e-6;
+/// Iterations of the fractal operations before a point counts as inside the set.
+pub const FRACTAL_ITERATIONS: u32 = 64;
+/// Escape radius of the fractal operations; large values make smoothing more accurate.
+pub const ESCAPE_RADIUS: f64 = 256.0;
+
 impl Operation {
+    /// Evaluates the tree at (`x`, `y`) and time `t`. Image leaves evaluate to 0.
     pub fn eval(&self, x: f64, y: f64, t: f64) -> Vec3 {
+        self.eval_with(x, y, t, &ImageSet::default())
+    }
+
+    /// Evaluates the tree, sampling image leaves from `images`.
+    pub fn eval_with(&self, x: f64, y: f64, t: f64, images: &ImageSet) -> Vec3 {
         match self {
-            Operation::Sum(a, b) => a.eval(x, y, t) + b.eval(x, y, t),
-            Operation::Product(a, b) => a.eval(x, y, t) * b.eval(x, y, t),
+            Operation::Sum(a, b) => a.eval_with(x, y, t, images) + b.eval_with(x, y, t, images),
+            Operation::Product(a, b) => a.eval_with(x, y, t, images) * b.eval_with(x, y, t, images),
             Operation::Mod(a, b) => {
-                let a_val = a.eval(x, y, t);
-                let b_val = b.eval(x, y, t);
+                let a_val = a.eval_with(x, y, t, images);
+                let b_val = b.eval_with(x, y, t, images);
                 Vec3::new(
                     a_val.x().rem_euclid(b_val.x()),
                     a_val.y().rem_euclid(b_val.y()),
@@ -43,12 +124,12 @@ impl Operation {
                 let val = (x - center_x).hypot(y - center_y);
                 Vec3::new(val, val, val)
             }
-            Operation::Sine(a) => a.eval(x, y, t).map(|v| v.sin()),
-            Operation::Inverse(a) => Vec3::new(0.0, 0.0, 0.0) - a.eval(x, y, t),
+            Operation::Sine(a) => a.eval_with(x, y, t, images).map(|v| v.sin()),
+            Operation::Inverse(a) => Vec3::new(0.0, 0.0, 0.0) - a.eval_with(x, y, t, images),
             Operation::PerChannelMask(m, a, b, threshold) => {
-                let m_val = m.eval(x, y, t);
-                let a_val = a.eval(x, y, t);
-                let b_val = b.eval(x, y, t);
+                let m_val = m.eval_with(x, y, t, images);
+                let a_val = a.eval_with(x, y, t, images);
+                let b_val = b.eval_with(x, y, t, images);
                 Vec3::new(
                     if m_val.x() > *threshold {
                         a_val.x()
@@ -69,9 +150,9 @@ impl Operation {
             }
 
             Operation::BinaryMask(m, a, b, threshold) => {
-                let m_val = m.eval(x, y, t);
-                let a_val = a.eval(x, y, t);
-                let b_val = b.eval(x, y, t);
+                let m_val = m.eval_with(x, y, t, images);
+                let a_val = a.eval_with(x, y, t, images);
+                let b_val = b.eval_with(x, y, t, images);
                 if m_val.length() > *threshold {
                     a_val
                 } else {
@@ -79,13 +160,13 @@ impl Operation {
                 }
             }
             Operation::SmoothMix(weight, a, b) => {
-                let weight_val = weight.eval(x, y, t).length();
-                let a_val = a.eval(x, y, t);
-                let b_val = b.eval(x, y, t);
+                let weight_val = weight.eval_with(x, y, t, images).length();
+                let a_val = a.eval_with(x, y, t, images);
+                let b_val = b.eval_with(x, y, t, images);
                 (weight_val * a_val) + ((1.0 - weight_val) * b_val)
             }
             Operation::Well(input) => {
-                let input_val = input.eval(x, y, t);
+                let input_val = input.eval_with(x, y, t, images);
                 Vec3::new(
                     Self::well_fn(input_val.x()),
                     Self::well_fn(input_val.y()),
@@ -93,7 +174,7 @@ impl Operation {
                 )
             }
             Operation::Tent(input) => {
-                let input_val = input.eval(x, y, t);
+                let input_val = input.eval_with(x, y, t, images);
                 Vec3::new(
                     Self::tent_fn(input_val.x()),
                     Self::tent_fn(input_val.y()),
@@ -101,79 +182,308 @@ impl Operation {
                 )
             }
             Operation::RGB(r, g, b) => Vec3::new(
-                r.eval(x, y, t).x(),
-                g.eval(x, y, t).y(),
-                b.eval(x, y, t).z(),
+                r.eval_with(x, y, t, images).x(),
+                g.eval_with(x, y, t, images).y(),
+                b.eval_with(x, y, t, images).z(),
             ),
+            Operation::Cos(a) => a.eval_with(x, y, t, images).map(|v| v.cos()),
+            Operation::Tan(a) => a.eval_with(x, y, t, images).map(Self::tan_fn),
+            Operation::Atan2(a, b) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), Self::atan2_fn),
+            Operation::Exp(a) => a.eval_with(x, y, t, images).map(Self::exp_fn),
+            Operation::Log(a) => a.eval_with(x, y, t, images).map(Self::log_fn),
+            Operation::Pow(a, b) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), Self::pow_fn),
+            Operation::Abs(a) => a.eval_with(x, y, t, images).map(|v| v.abs()),
+            Operation::Sqrt(a) => a.eval_with(x, y, t, images).map(Self::sqrt_fn),
+            Operation::Min(a, b) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), f64::min),
+            Operation::Max(a, b) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), f64::max),
+            Operation::Clamp(a, low, high) => a
+                .eval_with(x, y, t, images)
+                .map(|v| Self::clamp_fn(v, *low, *high)),
+            Operation::Perlin(a, b, seed) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), |u, v| {
+                    noise::perlin(u, v, *seed)
+                }),
+            Operation::Simplex(a, b, seed) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), |u, v| {
+                    noise::simplex(u, v, *seed)
+                }),
+            Operation::ValueNoise(a, b, seed) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), |u, v| {
+                    noise::value(u, v, *seed)
+                }),
+            Operation::Worley(a, b, seed) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), |u, v| {
+                    noise::worley(u, v, *seed)
+                }),
+            Operation::Fbm(a, b, seed, octaves) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), |u, v| {
+                    noise::fbm(u, v, *seed, *octaves)
+                }),
+            Operation::Mandelbrot(a, b) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), |re, im| {
+                    Self::escape_time_fn(0.0, 0.0, re, im)
+                }),
+            Operation::Julia(a, b, c_re, c_im) => a
+                .eval_with(x, y, t, images)
+                .zip_map(b.eval_with(x, y, t, images), |re, im| {
+                    Self::escape_time_fn(re, im, *c_re, *c_im)
+                }),
+            Operation::Rotate(angle, a) => {
+                let (x, y) = Self::rotate_fn(x, y, *angle);
+                a.eval_with(x, y, t, images)
+            }
+            Operation::Scale(sx, sy, a) => a.eval_with(x * sx, y * sy, t, images),
+            Operation::Translate(dx, dy, a) => a.eval_with(x - dx, y - dy, t, images),
+            Operation::Polar(a) => {
+                let (r, theta) = Self::polar_fn(x, y);
+                a.eval_with(r, theta, t, images)
+            }
+            Operation::Warp(dx, dy, a) => {
+                let dx = Self::mean_fn(dx.eval_with(x, y, t, images));
+                let dy = Self::mean_fn(dy.eval_with(x, y, t, images));
+                a.eval_with(x + dx, y + dy, t, images)
+            }
+            Operation::Mirror(a) => a.eval_with(x.abs(), y, t, images),
+            Operation::Kaleidoscope(n, a) => {
+                let (x, y) = Self::kaleidoscope_fn(x, y, *n);
+                a.eval_with(x, y, t, images)
+            }
+            Operation::Image(id) => images.sample(*id, x, y),
         }
     }
 
-    pub fn to_glsl(&self) -> String {
+    /// The direct children of this node, in the order they are evaluated.
+    pub fn children(&self) -> Vec<&Operation> {
         match self {
-            Operation::Sum(a, b) => format!("(({}) + ({}))", a.to_glsl(), b.to_glsl()),
-            Operation::Product(a, b) => format!("({} * {})", a.to_glsl(), b.to_glsl()),
-            Operation::Mod(a, b) => format!("mod({}, {})", a.to_glsl(), b.to_glsl()),
-            Operation::Constant(value) => format!("vec3({}, {}, {})", value, value, value),
-            Operation::VarX => "vec3(x,x,x)".to_string(),
-            Operation::VarY => "vec3(y,y,y)".to_string(),
-            Operation::VarT => "vec3(t,t,t)".to_string(),
-            Operation::Circle(cx, cy) => {
-                format!("vec3(distance(vec2(x, y), vec2({}, {})))", cx, cy)
-            }
-            Operation::Inverse(a) => format!("(vec3(0.0, 0.0, 0.0) - {})", a.to_glsl()),
-            Operation::PerChannelMask(m, a, b, threshold) => {
-                let m = m.to_glsl();
-                let a = a.to_glsl();
-                let b = b.to_glsl();
-                format!(
-                    "vec3(({}.x > {} ? {}.x : {}.x), ({}.y > {} ? {}.y : {}.y), ({}.z > {} ? {}.z : {}.z))",
-                    m, threshold, a, b, m, threshold, a, b, m, threshold, a, b
-                )
-            }
-            Operation::Sine(a) => format!("sin({})", a.to_glsl()),
+            Operation::Constant(_)
+            | Operation::VarX
+            | Operation::VarY
+            | Operation::VarT
+            | Operation::Circle(..)
+            | Operation::Image(_) => vec![],
+            Operation::Sine(a)
+            | Operation::Inverse(a)
+            | Operation::Well(a)
+            | Operation::Tent(a)
+            | Operation::Cos(a)
+            | Operation::Tan(a)
+            | Operation::Exp(a)
+            | Operation::Log(a)
+            | Operation::Abs(a)
+            | Operation::Sqrt(a)
+            | Operation::Clamp(a, ..)
+            | Operation::Rotate(_, a)
+            | Operation::Scale(_, _, a)
+            | Operation::Translate(_, _, a)
+            | Operation::Polar(a)
+            | Operation::Mirror(a)
+            | Operation::Kaleidoscope(_, a) => vec![a],
+            Operation::Sum(a, b)
+            | Operation::Product(a, b)
+            | Operation::Mod(a, b)
+            | Operation::Atan2(a, b)
+            | Operation::Pow(a, b)
+            | Operation::Min(a, b)
+            | Operation::Max(a, b)
+            | Operation::Perlin(a, b, _)
+            | Operation::Simplex(a, b, _)
+            | Operation::ValueNoise(a, b, _)
+            | Operation::Worley(a, b, _)
+            | Operation::Fbm(a, b, ..)
+            | Operation::Mandelbrot(a, b)
+            | Operation::Julia(a, b, ..) => vec![a, b],
+            Operation::PerChannelMask(a, b, c, _)
+            | Operation::BinaryMask(a, b, c, _)
+            | Operation::SmoothMix(a, b, c)
+            | Operation::RGB(a, b, c)
+            | Operation::Warp(a, b, c) => vec![a, b, c],
+        }
+    }
 
-            Operation::BinaryMask(m, a, b, threshold) => {
-                let m = m.to_glsl();
-                let a = a.to_glsl();
-                let b = b.to_glsl();
-                format!("(length({}) > {} ? {} : {})", m, threshold, a, b)
-            }
-
-            Operation::SmoothMix(weight, a, b) => format!(
-                "(({} * {}) + ((1.0 - {}) * {}))",
-                weight.to_glsl(),
-                a.to_glsl(),
-                weight.to_glsl(),
-                b.to_glsl()
-            ),
-            Operation::Well(a) => format!(
-                "vec3(well_fn({}.x), well_fn({}.y), well_fn({}.z))",
-                a.to_glsl(),
-                a.to_glsl(),
-                a.to_glsl()
-            ),
-            Operation::Tent(a) => format!(
-                "vec3(tent_fn({}.x), tent_fn({}.y), tent_fn({}.z))",
-                a.to_glsl(),
-                a.to_glsl(),
-                a.to_glsl()
-            ),
-            Operation::RGB(r, g, b) => format!(
-                "vec3({}.x, {}.y, {}.z)",
-                r.to_glsl(),
-                g.to_glsl(),
-                b.to_glsl()
-            ),
+    /// Number of nodes in the tree rooted at this node.
+    pub fn node_count(&self) -> usize {
+        1 + self
+            .children()
+            .into_iter()
+            .map(Operation::node_count)
+            .sum::<usize>()
+    }
+
+    /// Whether this node evaluates its last child at transformed coordinates.
+    pub(crate) fn is_transform(&self) -> bool {
+        matches!(
+            self,
+            Operation::Rotate(..)
+                | Operation::Scale(..)
+                | Operation::Translate(..)
+                | Operation::Polar(_)
+                | Operation::Warp(..)
+                | Operation::Mirror(_)
+                | Operation::Kaleidoscope(..)
+        )
+    }
+
+    /// Returns this node with its children replaced by `children`, given in
+    /// the order of [`Operation::children`].
+    pub(crate) fn with_children(&self, children: Vec<Operation>) -> Operation {
+        let mut children = children.into_iter().map(Box::new);
+        let mut next = || children.next().expect("too few children");
+        match self {
+            Operation::Constant(_)
+            | Operation::VarX
+            | Operation::VarY
+            | Operation::VarT
+            | Operation::Circle(..)
+            | Operation::Image(_) => self.clone(),
+            Operation::Sum(..) => Operation::Sum(next(), next()),
+            Operation::Product(..) => Operation::Product(next(), next()),
+            Operation::Mod(..) => Operation::Mod(next(), next()),
+            Operation::Sine(_) => Operation::Sine(next()),
+            Operation::Inverse(_) => Operation::Inverse(next()),
+            Operation::PerChannelMask(_, _, _, threshold) => {
+                Operation::PerChannelMask(next(), next(), next(), *threshold)
+            }
+            Operation::BinaryMask(_, _, _, threshold) => {
+                Operation::BinaryMask(next(), next(), next(), *threshold)
+            }
+            Operation::SmoothMix(..) => Operation::SmoothMix(next(), next(), next()),
+            Operation::Well(_) => Operation::Well(next()),
+            Operation::Tent(_) => Operation::Tent(next()),
+            Operation::RGB(..) => Operation::RGB(next(), next(), next()),
+            Operation::Cos(_) => Operation::Cos(next()),
+            Operation::Tan(_) => Operation::Tan(next()),
+            Operation::Atan2(..) => Operation::Atan2(next(), next()),
+            Operation::Exp(_) => Operation::Exp(next()),
+            Operation::Log(_) => Operation::Log(next()),
+            Operation::Pow(..) => Operation::Pow(next(), next()),
+            Operation::Abs(_) => Operation::Abs(next()),
+            Operation::Sqrt(_) => Operation::Sqrt(next()),
+            Operation::Min(..) => Operation::Min(next(), next()),
+            Operation::Max(..) => Operation::Max(next(), next()),
+            Operation::Clamp(_, low, high) => Operation::Clamp(next(), *low, *high),
+            Operation::Perlin(_, _, seed) => Operation::Perlin(next(), next(), *seed),
+            Operation::Simplex(_, _, seed) => Operation::Simplex(next(), next(), *seed),
+            Operation::ValueNoise(_, _, seed) => Operation::ValueNoise(next(), next(), *seed),
+            Operation::Worley(_, _, seed) => Operation::Worley(next(), next(), *seed),
+            Operation::Fbm(_, _, seed, octaves) => Operation::Fbm(next(), next(), *seed, *octaves),
+            Operation::Mandelbrot(..) => Operation::Mandelbrot(next(), next()),
+            Operation::Julia(_, _, c_re, c_im) => Operation::Julia(next(), next(), *c_re, *c_im),
+            Operation::Rotate(angle, _) => Operation::Rotate(*angle, next()),
+            Operation::Scale(sx, sy, _) => Operation::Scale(*sx, *sy, next()),
+            Operation::Translate(dx, dy, _) => Operation::Translate(*dx, *dy, next()),
+            Operation::Polar(_) => Operation::Polar(next()),
+            Operation::Warp(..) => Operation::Warp(next(), next(), next()),
+            Operation::Mirror(_) => Operation::Mirror(next()),
+            Operation::Kaleidoscope(n, _) => Operation::Kaleidoscope(*n, next()),
         }
     }
 
-    fn well_fn(x: f64) -> f64 {
+    /// GLSL 330 expression computing the tree as a `vec3` from `float`
+    /// variables `x`, `y` and `t`. It calls the functions returned by
+    /// [`Operation::glsl_functions`], and the helpers of the
+    /// [`Glsl330`] fragment shader.
+    pub fn to_glsl(&self) -> String {
+        let (name, _) = tree_functions(&Glsl330, self);
+        format!("{}(vec2(x, y), t)", name)
+    }
+
+    /// GLSL 330 definitions of the functions used by [`Operation::to_glsl`].
+    pub fn glsl_functions(&self) -> String {
+        tree_functions(&Glsl330, self).1
+    }
+
+    pub(crate) fn well_fn(x: f64) -> f64 {
         (1.0 - 2.0 / (1.0 + x * x)).powi(8)
     }
 
-    fn tent_fn(x: f64) -> f64 {
+    pub(crate) fn tent_fn(x: f64) -> f64 {
         1.0 - 2.0 * x.abs()
     }
+
+    pub(crate) fn tan_fn(x: f64) -> f64 {
+        x.tan().clamp(-TAN_LIMIT, TAN_LIMIT)
+    }
+
+    pub(crate) fn atan2_fn(y: f64, x: f64) -> f64 {
+        y.atan2(x) / std::f64::consts::PI
+    }
+
+    pub(crate) fn exp_fn(x: f64) -> f64 {
+        x.min(EXP_LIMIT).exp()
+    }
+
+    pub(crate) fn log_fn(x: f64) -> f64 {
+        x.abs().max(EPSILON).ln()
+    }
+
+    pub(crate) fn pow_fn(x: f64, y: f64) -> f64 {
+        x.abs().max(EPSILON).powf(y)
+    }
+
+    pub(crate) fn sqrt_fn(x: f64) -> f64 {
+        x.abs().sqrt()
+    }
+
+    /// Iterates z² + c from z and returns the smooth iteration count at which
+    /// |z| exceeds `ESCAPE_RADIUS`, mapped from [0, `FRACTAL_ITERATIONS`] to
+    /// [-1, 1]. Points that do not escape return 1.
+    pub(crate) fn escape_time_fn(mut z_re: f64, mut z_im: f64, c_re: f64, c_im: f64) -> f64 {
+        for n in 0..FRACTAL_ITERATIONS {
+            let (re2, im2) = (z_re * z_re, z_im * z_im);
+            if re2 + im2 > ESCAPE_RADIUS * ESCAPE_RADIUS {
+                let log_abs = (re2 + im2).ln() / 2.0;
+                let smooth = n as f64 + 1.0 - log_abs.log2();
+                let max = FRACTAL_ITERATIONS as f64;
+                return 2.0 * smooth.clamp(0.0, max) / max - 1.0;
+            }
+            z_im = 2.0 * z_re * z_im + c_im;
+            z_re = re2 - im2 + c_re;
+        }
+        1.0
+    }
+
+    /// Rotates the plane by `angle`, so the child appears rotated by `-angle`.
+    pub(crate) fn rotate_fn(x: f64, y: f64, angle: f64) -> (f64, f64) {
+        let (sin, cos) = angle.sin_cos();
+        (x * cos + y * sin, y * cos - x * sin)
+    }
+
+    /// Distance from the origin, and the angle divided by π.
+    pub(crate) fn polar_fn(x: f64, y: f64) -> (f64, f64) {
+        (x.hypot(y), Self::atan2_fn(y, x))
+    }
+
+    /// Maps the angle around the origin into the first half of a wedge of
+    /// `2π / n`, mirroring every other half.
+    pub(crate) fn kaleidoscope_fn(x: f64, y: f64, n: u32) -> (f64, f64) {
+        let wedge = std::f64::consts::TAU / n.max(1) as f64;
+        let angle = (y.atan2(x).rem_euclid(wedge) - wedge / 2.0).abs();
+        let r = x.hypot(y);
+        (r * angle.cos(), r * angle.sin())
+    }
+
+    pub(crate) fn mean_fn(v: Vec3) -> f64 {
+        (v.x() + v.y() + v.z()) / 3.0
+    }
+
+    /// Unlike `f64::clamp`, does not panic when `low > high`.
+    pub(crate) fn clamp_fn(x: f64, low: f64, high: f64) -> f64 {
+        x.max(low).min(high)
+    }
 }
 
 impl Display for Operation {
@@ -201,6 +511,500 @@ impl Display for Operation {
             Operation::Well(a) => write!(f, "well({})", a),
             Operation::Tent(a) => write!(f, "tent({})", a),
             Operation::RGB(r, g, b) => write!(f, "rgb({}, {}, {})", r, g, b),
+            Operation::Cos(a) => write!(f, "cos({})", a),
+            Operation::Tan(a) => write!(f, "tan({})", a),
+            Operation::Atan2(a, b) => write!(f, "atan2({}, {})", a, b),
+            Operation::Exp(a) => write!(f, "exp({})", a),
+            Operation::Log(a) => write!(f, "log({})", a),
+            Operation::Pow(a, b) => write!(f, "pow({}, {})", a, b),
+            Operation::Abs(a) => write!(f, "abs({})", a),
+            Operation::Sqrt(a) => write!(f, "sqrt({})", a),
+            Operation::Min(a, b) => write!(f, "min({}, {})", a, b),
+            Operation::Max(a, b) => write!(f, "max({}, {})", a, b),
+            Operation::Clamp(a, low, high) => write!(f, "clamp({}, {}, {})", a, low, high),
+            Operation::Perlin(a, b, seed) => write!(f, "perlin({}, {}, {})", a, b, seed),
+            Operation::Simplex(a, b, seed) => write!(f, "simplex({}, {}, {})", a, b, seed),
+            Operation::ValueNoise(a, b, seed) => write!(f, "value_noise({}, {}, {})", a, b, seed),
+            Operation::Worley(a, b, seed) => write!(f, "worley({}, {}, {})", a, b, seed),
+            Operation::Fbm(a, b, seed, octaves) => {
+                write!(f, "fbm({}, {}, {}, {})", a, b, seed, octaves)
+            }
+            Operation::Mandelbrot(a, b) => write!(f, "mandelbrot({}, {})", a, b),
+            Operation::Julia(a, b, c_re, c_im) => {
+                write!(f, "julia({}, {}, {}, {})", a, b, c_re, c_im)
+            }
+            Operation::Rotate(angle, a) => write!(f, "rotate({}, {})", angle, a),
+            Operation::Scale(sx, sy, a) => write!(f, "scale({}, {}, {})", sx, sy, a),
+            Operation::Translate(dx, dy, a) => write!(f, "translate({}, {}, {})", dx, dy, a),
+            Operation::Polar(a) => write!(f, "polar({})", a),
+            Operation::Warp(dx, dy, a) => write!(f, "warp({}, {}, {})", dx, dy, a),
+            Operation::Mirror(a) => write!(f, "mirror({})", a),
+            Operation::Kaleidoscope(n, a) => write!(f, "kaleidoscope({}, {})", n, a),
+            Operation::Image(id) => write!(f, "image({})", id),
+        }
+    }
+}
+
+/// Error returned when parsing the textual form of an [`Operation`] fails.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("line {line}, column {column}: expected {expected}, found {found}")]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub expected: String,
+    pub found: String,
+}
+
+/// Parses an expression in the format produced by `Display for Operation`.
+///
+/// A leading minus directly followed by a number is read as a negative
+/// constant, so `-0.5` yields `Constant(-0.5)` rather than
+/// `Inverse(Constant(0.5))`; both evaluate identically.
+pub fn parse_expr(input: &str) -> Result<Operation, ParseError> {
+    let mut parser = Parser::new(input);
+    let op = parser.parse_expr()?;
+    parser.expect_end()?;
+    Ok(op)
+}
+
+impl FromStr for Operation {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_expr(s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    LParen,
+    RParen,
+    Comma,
+    Plus,
+    Star,
+    Percent,
+    Minus,
+    End,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "number `{}`", value),
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Plus => write!(f, "`+`"),
+            Token::Star => write!(f, "`*`"),
+            Token::Percent => write!(f, "`%`"),
+            Token::Minus => write!(f, "`-`"),
+            Token::End => write!(f, "end of input"),
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    peeked: Option<(Token, usize, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Parser {
+            chars: input.chars().peekable(),
+            line: 1,
+            column: 1,
+            peeked: None,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn lex(&mut self) -> Result<(Token, usize, usize), ParseError> {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.bump();
+        }
+        let (line, column) = (self.line, self.column);
+        let Some(c) = self.bump() else {
+            return Ok((Token::End, line, column));
+        };
+        let token = match c {
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '+' => Token::Plus,
+            '*' => Token::Star,
+            '%' => Token::Percent,
+            '-' => Token::Minus,
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut text = String::from(c);
+                while let Some(&next) = self.chars.peek() {
+                    let exponent_sign = (next == '-' || next == '+') && text.ends_with(['e', 'E']);
+                    if !(next.is_ascii_digit() || matches!(next, '.' | 'e' | 'E') || exponent_sign)
+                    {
+                        break;
+                    }
+                    text.push(next);
+                    self.bump();
+                }
+                let value = text.parse().map_err(|_| ParseError {
+                    line,
+                    column,
+                    expected: "a number".to_string(),
+                    found: format!("`{}`", text),
+                })?;
+                Token::Number(value)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = String::from(c);
+                while let Some(&next) = self.chars.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_') {
+                        break;
+                    }
+                    name.push(next);
+                    self.bump();
+                }
+                Token::Ident(name)
+            }
+            other => {
+                return Err(ParseError {
+                    line,
+                    column,
+                    expected: "an expression".to_string(),
+                    found: format!("`{}`", other),
+                })
+            }
+        };
+        Ok((token, line, column))
+    }
+
+    fn peek(&mut self) -> Result<&Token, ParseError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lex()?);
+        }
+        Ok(&self.peeked.as_ref().unwrap().0)
+    }
+
+    fn next(&mut self) -> Result<(Token, usize, usize), ParseError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lex(),
+        }
+    }
+
+    fn error(expected: &str, (found, line, column): (Token, usize, usize)) -> ParseError {
+        ParseError {
+            line,
+            column,
+            expected: expected.to_string(),
+            found: found.to_string(),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        let next = self.next()?;
+        if next.0 == expected {
+            Ok(())
+        } else {
+            Err(Self::error(&expected.to_string(), next))
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<(), ParseError> {
+        self.expect(Token::End)
+    }
+
+    fn parse_number(&mut self) -> Result<f64, ParseError> {
+        let negative = *self.peek()? == Token::Minus;
+        if negative {
+            self.next()?;
+        }
+        let next = self.next()?;
+        let value = match next {
+            (Token::Number(value), ..) => value,
+            (Token::Ident(ref name), ..) if name == "inf" => f64::INFINITY,
+            (Token::Ident(ref name), ..) if name == "NaN" => f64::NAN,
+            other => return Err(Self::error("a number", other)),
+        };
+        Ok(if negative { -value } else { value })
+    }
+
+    fn parse_integer(&mut self) -> Result<u32, ParseError> {
+        match self.next()? {
+            (Token::Number(value), ..)
+                if value.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&value) =>
+            {
+                Ok(value as u32)
+            }
+            other => Err(Self::error("an unsigned integer", other)),
+        }
+    }
+
+    fn parse_args(&mut self, name: &str, count: usize) -> Result<Vec<Operation>, ParseError> {
+        self.expect(Token::LParen)?;
+        let mut args = Vec::with_capacity(count);
+        for i in 0..count {
+            if i > 0 {
+                self.expect(Token::Comma)?;
+            }
+            args.push(self.parse_expr()?);
+        }
+        let next = self.next()?;
+        if next.0 != Token::RParen {
+            return Err(Self::error(
+                &format!("`)` after {} arguments to `{}`", count, name),
+                next,
+            ));
+        }
+        Ok(args)
+    }
+
+    fn parse_mask(&mut self) -> Result<[Box<Operation>; 3], ParseError> {
+        self.expect(Token::LParen)?;
+        let m = self.parse_expr()?;
+        self.expect(Token::Comma)?;
+        let a = self.parse_expr()?;
+        self.expect(Token::Comma)?;
+        let b = self.parse_expr()?;
+        self.expect(Token::Comma)?;
+        Ok([m.into(), a.into(), b.into()])
+    }
+
+    fn parse_expr(&mut self) -> Result<Operation, ParseError> {
+        let next = self.next()?;
+        match next {
+            (Token::LParen, ..) => {
+                let lhs = self.parse_expr()?;
+                let op = self.next()?;
+                let rhs = self.parse_expr()?;
+                self.expect(Token::RParen)?;
+                match op.0 {
+                    Token::Plus => Ok(Operation::Sum(lhs.into(), rhs.into())),
+                    Token::Star => Ok(Operation::Product(lhs.into(), rhs.into())),
+                    Token::Percent => Ok(Operation::Mod(lhs.into(), rhs.into())),
+                    _ => Err(Self::error("`+`, `*` or `%`", op)),
+                }
+            }
+            (Token::Minus, ..) => match self.peek()? {
+                Token::Number(_) => Ok(Operation::Constant(-self.parse_number()?)),
+                Token::Ident(name) if name == "inf" || name == "NaN" => {
+                    Ok(Operation::Constant(-self.parse_number()?))
+                }
+                _ => Ok(Operation::Inverse(self.parse_expr()?.into())),
+            },
+            (Token::Number(value), ..) => Ok(Operation::Constant(value)),
+            (Token::Ident(name), line, column) => match name.as_str() {
+                "x" => Ok(Operation::VarX),
+                "y" => Ok(Operation::VarY),
+                "t" => Ok(Operation::VarT),
+                "inf" => Ok(Operation::Constant(f64::INFINITY)),
+                "image" => {
+                    self.expect(Token::LParen)?;
+                    let id = self.parse_integer()?;
+                    self.expect(Token::RParen)?;
+                    Ok(Operation::Image(id))
+                }
+                "NaN" => Ok(Operation::Constant(f64::NAN)),
+                "circle" => {
+                    self.expect(Token::LParen)?;
+                    let center_x = self.parse_number()?;
+                    self.expect(Token::Comma)?;
+                    let center_y = self.parse_number()?;
+                    self.expect(Token::RParen)?;
+                    Ok(Operation::Circle(center_x, center_y))
+                }
+                "per_channel_mask" | "binary_mask" => {
+                    let [m, a, b] = self.parse_mask()?;
+                    let threshold = self.parse_number()?;
+                    self.expect(Token::RParen)?;
+                    if name == "per_channel_mask" {
+                        Ok(Operation::PerChannelMask(m, a, b, threshold))
+                    } else {
+                        Ok(Operation::BinaryMask(m, a, b, threshold))
+                    }
+                }
+                "sin" | "cos" | "tan" | "exp" | "log" | "abs" | "sqrt" | "well" | "tent" => {
+                    let a = self.parse_args(&name, 1)?.remove(0).into();
+                    Ok(match name.as_str() {
+                        "sin" => Operation::Sine(a),
+                        "cos" => Operation::Cos(a),
+                        "tan" => Operation::Tan(a),
+                        "exp" => Operation::Exp(a),
+                        "log" => Operation::Log(a),
+                        "abs" => Operation::Abs(a),
+                        "sqrt" => Operation::Sqrt(a),
+                        "well" => Operation::Well(a),
+                        _ => Operation::Tent(a),
+                    })
+                }
+                "atan2" | "pow" | "min" | "max" | "mandelbrot" => {
+                    let mut args = self.parse_args(&name, 2)?.into_iter().map(Box::new);
+                    let (a, b) = (args.next().unwrap(), args.next().unwrap());
+                    Ok(match name.as_str() {
+                        "atan2" => Operation::Atan2(a, b),
+                        "pow" => Operation::Pow(a, b),
+                        "min" => Operation::Min(a, b),
+                        "max" => Operation::Max(a, b),
+                        _ => Operation::Mandelbrot(a, b),
+                    })
+                }
+                "clamp" => {
+                    self.expect(Token::LParen)?;
+                    let a = self.parse_expr()?;
+                    self.expect(Token::Comma)?;
+                    let low = self.parse_number()?;
+                    self.expect(Token::Comma)?;
+                    let high = self.parse_number()?;
+                    self.expect(Token::RParen)?;
+                    Ok(Operation::Clamp(a.into(), low, high))
+                }
+                "perlin" | "simplex" | "value_noise" | "worley" | "fbm" => {
+                    self.expect(Token::LParen)?;
+                    let a = self.parse_expr()?.into();
+                    self.expect(Token::Comma)?;
+                    let b = self.parse_expr()?.into();
+                    self.expect(Token::Comma)?;
+                    let seed = self.parse_integer()?;
+                    let op = match name.as_str() {
+                        "perlin" => Operation::Perlin(a, b, seed),
+                        "simplex" => Operation::Simplex(a, b, seed),
+                        "value_noise" => Operation::ValueNoise(a, b, seed),
+                        "worley" => Operation::Worley(a, b, seed),
+                        _ => {
+                            self.expect(Token::Comma)?;
+                            Operation::Fbm(a, b, seed, self.parse_integer()?)
+                        }
+                    };
+                    self.expect(Token::RParen)?;
+                    Ok(op)
+                }
+                "julia" => {
+                    self.expect(Token::LParen)?;
+                    let a = self.parse_expr()?;
+                    self.expect(Token::Comma)?;
+                    let b = self.parse_expr()?;
+                    self.expect(Token::Comma)?;
+                    let c_re = self.parse_number()?;
+                    self.expect(Token::Comma)?;
+                    let c_im = self.parse_number()?;
+                    self.expect(Token::RParen)?;
+                    Ok(Operation::Julia(a.into(), b.into(), c_re, c_im))
+                }
+                "rotate" => {
+                    self.expect(Token::LParen)?;
+                    let angle = self.parse_number()?;
+                    self.expect(Token::Comma)?;
+                    let a = self.parse_expr()?;
+                    self.expect(Token::RParen)?;
+                    Ok(Operation::Rotate(angle, a.into()))
+                }
+                "scale" | "translate" => {
+                    self.expect(Token::LParen)?;
+                    let u = self.parse_number()?;
+                    self.expect(Token::Comma)?;
+                    let v = self.parse_number()?;
+                    self.expect(Token::Comma)?;
+                    let a = self.parse_expr()?.into();
+                    self.expect(Token::RParen)?;
+                    if name == "scale" {
+                        Ok(Operation::Scale(u, v, a))
+                    } else {
+                        Ok(Operation::Translate(u, v, a))
+                    }
+                }
+                "polar" | "mirror" => {
+                    let a = self.parse_args(&name, 1)?.remove(0).into();
+                    if name == "polar" {
+                        Ok(Operation::Polar(a))
+                    } else {
+                        Ok(Operation::Mirror(a))
+                    }
+                }
+                "kaleidoscope" => {
+                    self.expect(Token::LParen)?;
+                    let n = self.parse_integer()?;
+                    self.expect(Token::Comma)?;
+                    let a = self.parse_expr()?;
+                    self.expect(Token::RParen)?;
+                    Ok(Operation::Kaleidoscope(n, a.into()))
+                }
+                "smooth_mix" | "rgb" | "warp" => {
+                    let mut args = self.parse_args(&name, 3)?.into_iter().map(Box::new);
+                    let (a, b, c) = (
+                        args.next().unwrap(),
+                        args.next().unwrap(),
+                        args.next().unwrap(),
+                    );
+                    Ok(match name.as_str() {
+                        "rgb" => Operation::RGB(a, b, c),
+                        "warp" => Operation::Warp(a, b, c),
+                        _ => Operation::SmoothMix(a, b, c),
+                    })
+                }
+                _ => Err(Self::error(
+                    "an expression",
+                    (Token::Ident(name), line, column),
+                )),
+            },
+            other => Err(Self::error("an expression", other)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::{ArtGrammar, OperationWeights, PerrigSongGrammar, RandomArtGrammar};
+    use crate::seed::Seed;
+
+    fn assert_round_trip(tree: &Operation) {
+        let text = tree.to_string();
+        let parsed = parse_expr(&text).unwrap_or_else(|err| panic!("{}: {}", err, text));
+        assert_eq!(parsed.to_string(), text);
+    }
+
+    #[test]
+    fn parse_reproduces_printed_trees() {
+        for i in 0..100 {
+            let seed = Seed::from_string(&format!("round trip {}", i));
+            let depth = i % 8;
+            assert_round_trip(&PerrigSongGrammar::from_seed(&seed).generate_tree(depth));
+            assert_round_trip(&RandomArtGrammar::from_seed(&seed).generate_tree(depth));
+            assert_round_trip(
+                &RandomArtGrammar::from_seed(&seed)
+                    .with_weights(OperationWeights::uniform().with_image_count(3))
+                    .generate_tree(depth),
+            );
+        }
+    }
+
+    #[test]
+    fn parse_reads_negative_constants() {
+        assert_eq!(parse_expr("-0.5"), Ok(Operation::Constant(-0.5)));
+        assert_eq!(
+            parse_expr("-x"),
+            Ok(Operation::Inverse(Operation::VarX.into()))
+        );
+        assert_round_trip(&Operation::Inverse(Operation::Constant(-0.5).into()));
+    }
+
+    #[test]
+    fn parse_reports_position() {
+        let err = parse_expr("(x +\n  )").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+    }
+}
@@ -0,0 +1,217 @@
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, RgbImage};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::utils::ImageWriteError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    /// One PNG per frame, numbered after the output file name (`art.png` becomes `art_0000.png`, ...).
+    PngSequence,
+    Gif,
+    Apng,
+}
+
+impl AnimationFormat {
+    /// Picks the format from the extension: `.gif` and `.apng` are single
+    /// animated files, anything else is written as a PNG sequence.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("gif") => AnimationFormat::Gif,
+            Some("apng") => AnimationFormat::Apng,
+            _ => AnimationFormat::PngSequence,
+        }
+    }
+}
+
+/// Frames rendered by `FileRenderer::render_animation`.
+///
+/// Frame `i` is rendered at `t = t_start + (t_end - t_start) * i / (frames - 1)`,
+/// so both ends of the range are included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Animation {
+    pub frames: usize,
+    pub fps: u16,
+    pub t_start: f64,
+    pub t_end: f64,
+    pub format: AnimationFormat,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Animation {
+            frames: 60,
+            fps: 30,
+            t_start: -1.0,
+            t_end: 1.0,
+            format: AnimationFormat::Gif,
+        }
+    }
+}
+
+impl Animation {
+    pub fn frame_time(&self, frame: usize) -> f64 {
+        if self.frames <= 1 {
+            return self.t_start;
+        }
+        self.t_start + (self.t_end - self.t_start) * frame as f64 / (self.frames - 1) as f64
+    }
+
+    /// Writes the frames produced by `render_frame(t)` to `output_path`.
+    /// Frames are encoded as they are rendered, so only one is kept in memory.
+    pub(crate) fn write(
+        &self,
+        output_path: &str,
+        width: u32,
+        height: u32,
+        mut render_frame: impl FnMut(f64) -> RgbImage,
+    ) -> Result<(), ImageWriteError> {
+        let times = (0..self.frames).map(|frame| self.frame_time(frame));
+        match self.format {
+            AnimationFormat::PngSequence => {
+                for (frame, t) in times.enumerate() {
+                    render_frame(t).save(numbered_path(output_path, frame))?;
+                }
+            }
+            AnimationFormat::Gif => {
+                let mut encoder = GifEncoder::new(BufWriter::new(File::create(output_path)?));
+                encoder.set_repeat(Repeat::Infinite)?;
+                let delay = Delay::from_numer_denom_ms(1000, u32::from(self.fps.max(1)));
+                for t in times {
+                    let rgba = DynamicImage::ImageRgb8(render_frame(t)).into_rgba8();
+                    encoder.encode_frame(Frame::from_parts(rgba, 0, 0, delay))?;
+                }
+            }
+            AnimationFormat::Apng => {
+                let file = BufWriter::new(File::create(output_path)?);
+                let mut encoder = png::Encoder::new(file, width, height);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(self.frames as u32, 0)?;
+                encoder.set_frame_delay(1, self.fps.max(1))?;
+                let mut writer = encoder.write_header()?;
+                for t in times {
+                    writer.write_image_data(render_frame(t).as_raw())?;
+                }
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn numbered_path(output_path: &str, frame: usize) -> String {
+    let path = Path::new(output_path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+    path.with_file_name(format!("{}_{:04}.png", stem, frame))
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifDecoder;
+    use image::AnimationDecoder;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn animation(frames: usize, fps: u16, format: AnimationFormat) -> Animation {
+        Animation {
+            frames,
+            fps,
+            t_start: -1.0,
+            t_end: 2.0,
+            format,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("random_art_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes `animation` with frames whose red channel is the frame index.
+    fn write(animation: &Animation, path: &Path) {
+        let mut frame = 0;
+        animation
+            .write(path.to_str().unwrap(), 3, 2, |_| {
+                frame += 1;
+                RgbImage::from_pixel(3, 2, image::Rgb([frame * 10, 0, 0]))
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn frame_times_include_both_ends() {
+        let animation = animation(4, 30, AnimationFormat::Gif);
+        let times: Vec<f64> = (0..4).map(|frame| animation.frame_time(frame)).collect();
+        assert_eq!(times, [-1.0, 0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn single_frame_is_at_the_start() {
+        assert_eq!(animation(1, 30, AnimationFormat::Gif).frame_time(0), -1.0);
+    }
+
+    #[test]
+    fn format_follows_the_extension() {
+        assert_eq!(AnimationFormat::from_path("a.gif"), AnimationFormat::Gif);
+        assert_eq!(AnimationFormat::from_path("a.apng"), AnimationFormat::Apng);
+        assert_eq!(
+            AnimationFormat::from_path("a.png"),
+            AnimationFormat::PngSequence
+        );
+    }
+
+    #[test]
+    fn png_sequence_is_numbered_after_the_stem() {
+        let dir = temp_dir("sequence");
+        write(
+            &animation(3, 30, AnimationFormat::PngSequence),
+            &dir.join("art.png"),
+        );
+        for frame in 0..3 {
+            let image = image::open(dir.join(format!("art_{:04}.png", frame))).unwrap();
+            assert_eq!(image.to_rgb8().get_pixel(0, 0)[0], (frame as u8 + 1) * 10);
+        }
+        assert!(!dir.join("art.png").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gif_has_every_frame_with_the_frame_delay() {
+        let dir = temp_dir("gif");
+        let path = dir.join("art.gif");
+        write(&animation(5, 25, AnimationFormat::Gif), &path);
+        let decoder = GifDecoder::new(std::io::BufReader::new(File::open(&path).unwrap())).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 5);
+        for frame in frames {
+            assert_eq!(frame.delay().numer_denom_ms(), (40, 1));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn apng_has_every_frame_with_the_frame_delay() {
+        let dir = temp_dir("apng");
+        let path = dir.join("art.apng");
+        write(&animation(5, 24, AnimationFormat::Apng), &path);
+        let mut reader = png::Decoder::new(File::open(&path).unwrap())
+            .read_info()
+            .unwrap();
+        assert_eq!(reader.info().animation_control.unwrap().num_frames, 5);
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        for frame in 0..5 {
+            reader.next_frame(&mut buffer).unwrap();
+            let control = reader.info().frame_control.unwrap();
+            assert_eq!((control.delay_num, control.delay_den), (1, 24));
+            assert_eq!(buffer[0], (frame + 1) * 10);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,12 +1,36 @@
+use crate::compiled::CompiledExpr;
+use crate::images::ImageSet;
 use crate::operations::Operation;
-use crate::utils::{write_image, ImageWriteError};
+use crate::renderer::{Animation, RenderSpec, TimeMapping};
+use crate::utils::{to_image, write_image, ImageWriteError};
+use crate::vec3::Vec3;
+use image::RgbImage;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 pub struct FileRenderer {
     output_path: String,
+    spec: RenderSpec,
+    images: ImageSet,
 }
 impl FileRenderer {
     pub fn new(output_path: String) -> Self {
-        FileRenderer { output_path }
+        FileRenderer {
+            output_path,
+            spec: RenderSpec::default(),
+            images: ImageSet::default(),
+        }
+    }
+
+    pub fn with_spec(mut self, spec: RenderSpec) -> Self {
+        self.spec = spec;
+        self
+    }
+
+    /// Sets the images sampled by image leaves.
+    pub fn with_images(mut self, images: ImageSet) -> Self {
+        self.images = images;
+        self
     }
 
     pub fn render(
@@ -15,17 +39,135 @@ impl FileRenderer {
         y_res: usize,
         root: &Operation,
     ) -> Result<(), ImageWriteError> {
-        let mut values = Vec::with_capacity(x_res * y_res);
-        let t = 0.0;
-
-        for y in 0..y_res {
-            for x in 0..x_res {
-                let fx = 0.0 + (x as f64 / x_res as f64) * (1.0 - 0.0);
-                let fy = 0.0 + (y as f64 / y_res as f64) * (1.0 - 0.0);
-                let color = root.eval(fx, fy, t);
-                values.push(color);
-            }
+        let values = Self::evaluate(&self.spec, &self.images, x_res, y_res, root);
+        write_image(&self.output_path, x_res, y_res, &values, self.spec.color)
+    }
+
+    /// Renders the frames of `animation`, each at a fixed `t` from its range.
+    pub fn render_animation(
+        &mut self,
+        x_res: usize,
+        y_res: usize,
+        root: &Operation,
+        animation: &Animation,
+    ) -> Result<(), ImageWriteError> {
+        animation.write(&self.output_path, x_res as u32, y_res as u32, |t| {
+            let spec = RenderSpec {
+                time: TimeMapping::Fixed(t),
+                ..self.spec
+            };
+            let values = Self::evaluate(&spec, &self.images, x_res, y_res, root);
+            to_image(x_res, y_res, &values, spec.color)
+        })
+    }
+
+    /// Renders `root` to an in-memory image instead of a file. Image leaves
+    /// evaluate to 0.
+    pub fn render_image(
+        spec: &RenderSpec,
+        x_res: usize,
+        y_res: usize,
+        root: &Operation,
+    ) -> RgbImage {
+        to_image(
+            x_res,
+            y_res,
+            &Self::evaluate(spec, &ImageSet::default(), x_res, y_res, root),
+            spec.color,
+        )
+    }
+
+    /// Evaluates every pixel, one row per batch of the compiled expression.
+    /// With the `parallel` feature rows are spread over the rayon thread pool;
+    /// each pixel is still evaluated on its own, so the output is identical to
+    /// the single-threaded path.
+    fn evaluate(
+        spec: &RenderSpec,
+        images: &ImageSet,
+        x_res: usize,
+        y_res: usize,
+        root: &Operation,
+    ) -> Vec<Vec3> {
+        let mut values = vec![Vec3::new(0.0, 0.0, 0.0); x_res * y_res];
+        let compiled = CompiledExpr::compile(root);
+        let render_row = |(y, row): (usize, &mut [Vec3])| {
+            Self::evaluate_row(spec, images, &compiled, y, y_res, row)
+        };
+
+        #[cfg(feature = "parallel")]
+        values
+            .par_chunks_mut(x_res.max(1))
+            .enumerate()
+            .for_each(render_row);
+        #[cfg(not(feature = "parallel"))]
+        values
+            .chunks_mut(x_res.max(1))
+            .enumerate()
+            .for_each(render_row);
+
+        values
+    }
+
+    /// Evaluates row `y` of the image into `row`, which holds one row of pixels.
+    fn evaluate_row(
+        spec: &RenderSpec,
+        images: &ImageSet,
+        compiled: &CompiledExpr,
+        y: usize,
+        y_res: usize,
+        row: &mut [Vec3],
+    ) {
+        let x_res = row.len();
+        let (xs, ys): (Vec<f64>, Vec<f64>) = (0..x_res)
+            .map(|x| spec.pixel_to_point(x, y, x_res, y_res))
+            .unzip();
+        compiled.eval_batch_with(&xs, &ys, spec.time.t(0.0), images, row);
+    }
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod tests {
+    use super::*;
+    use crate::grammar::{ArtGrammar, OperationWeights, RandomArtGrammar};
+    use crate::seed::Seed;
+
+    fn render_sequential(
+        spec: &RenderSpec,
+        x_res: usize,
+        y_res: usize,
+        root: &Operation,
+    ) -> RgbImage {
+        let compiled = CompiledExpr::compile(root);
+        let images = ImageSet::default();
+        let mut values = vec![Vec3::new(0.0, 0.0, 0.0); x_res * y_res];
+        for (y, row) in values.chunks_mut(x_res).enumerate() {
+            FileRenderer::evaluate_row(spec, &images, &compiled, y, y_res, row);
+        }
+        to_image(x_res, y_res, &values, spec.color)
+    }
+
+    #[test]
+    fn parallel_rendering_matches_sequential() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(3)
+            .build()
+            .unwrap();
+        let spec = RenderSpec {
+            time: TimeMapping::Fixed(0.25),
+            ..RenderSpec::default()
+        };
+        for i in 0..20 {
+            let seed = Seed::from_string(&format!("parallel {}", i));
+            let root = RandomArtGrammar::from_seed(&seed)
+                .with_weights(OperationWeights::uniform())
+                .generate_tree(6);
+            let parallel = pool.install(|| FileRenderer::render_image(&spec, 61, 37, &root));
+            assert_eq!(
+                parallel.as_raw(),
+                render_sequential(&spec, 61, 37, &root).as_raw(),
+                "{}",
+                root
+            );
         }
-        write_image(&self.output_path, x_res, y_res, &values)
     }
 }
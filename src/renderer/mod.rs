@@ -1,5 +1,9 @@
+mod animation;
 mod file_renderer;
+mod spec;
 mod window_renderer;
 
+pub use animation::{Animation, AnimationFormat};
 pub use file_renderer::FileRenderer;
-pub use window_renderer::WindowRenderer;
+pub use spec::{ColorMapping, Domain, RenderSpec, TimeMapping};
+pub use window_renderer::{TreeParams, WindowRenderError, WindowRenderer};
@@ -0,0 +1,164 @@
+use crate::shader::float_literal;
+use crate::vec3::Vec3;
+
+/// Region of the plane shown by a renderer.
+///
+/// The shorter image side spans `center ± half_extent`; the longer side
+/// extends equally beyond it, so non-square images are not stretched.
+/// Pixel row 0 is the top of the image and has the smallest y.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Domain {
+    pub center_x: f64,
+    pub center_y: f64,
+    pub half_extent: f64,
+}
+
+impl Domain {
+    /// The [-1, 1] square used by the grammars' constants and circle centers.
+    pub fn signed() -> Self {
+        Domain {
+            center_x: 0.0,
+            center_y: 0.0,
+            half_extent: 1.0,
+        }
+    }
+
+    /// The [0, 1] square, as used by the original file renderer.
+    pub fn unit() -> Self {
+        Domain {
+            center_x: 0.5,
+            center_y: 0.5,
+            half_extent: 0.5,
+        }
+    }
+}
+
+/// How the `t` variable is derived from the elapsed time in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeMapping {
+    Fixed(f64),
+    /// `t = sin(seconds)`, so animations loop smoothly within [-1, 1].
+    Sine,
+}
+
+impl TimeMapping {
+    pub fn t(&self, seconds: f64) -> f64 {
+        match self {
+            TimeMapping::Fixed(t) => *t,
+            TimeMapping::Sine => seconds.sin(),
+        }
+    }
+
+    /// GLSL expression for `t`, given a `time` uniform in seconds.
+    pub fn to_glsl(&self) -> String {
+        match self {
+            TimeMapping::Fixed(t) => float_literal(*t),
+            TimeMapping::Sine => "sin(time)".to_string(),
+        }
+    }
+}
+
+/// How evaluated values are turned into colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMapping {
+    /// Maps [-1, 1] to [0, 1], then clamps.
+    Signed,
+    /// Clamps values to [0, 1].
+    Clamp,
+}
+
+impl ColorMapping {
+    pub fn map(&self, color: Vec3) -> [u8; 3] {
+        let channel = |v: f64| {
+            let v = match self {
+                ColorMapping::Signed => (v + 1.0) / 2.0,
+                ColorMapping::Clamp => v,
+            };
+            // Round like the GPU does when writing to an 8-bit framebuffer
+            (v.clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+        [channel(color.x()), channel(color.y()), channel(color.z())]
+    }
+
+    /// Body of a GLSL `vec4 map_color(vec3 rgb)` function.
+    pub fn to_glsl(&self) -> &'static str {
+        match self {
+            ColorMapping::Signed => "return vec4(clamp((rgb + 1.0) / 2.0, 0.0, 1.0), 1.0);",
+            ColorMapping::Clamp => "return vec4(clamp(rgb, 0.0, 1.0), 1.0);",
+        }
+    }
+}
+
+/// Everything besides the tree that determines a rendered image.
+///
+/// `FileRenderer` and `WindowRenderer` both follow the spec, so the window
+/// preview shows the same image that is saved to disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSpec {
+    pub domain: Domain,
+    pub time: TimeMapping,
+    pub color: ColorMapping,
+}
+
+impl Default for RenderSpec {
+    fn default() -> Self {
+        RenderSpec {
+            domain: Domain::signed(),
+            time: TimeMapping::Sine,
+            color: ColorMapping::Signed,
+        }
+    }
+}
+
+impl RenderSpec {
+    /// Maps the center of pixel (`x`, `y`) to a point in the domain.
+    pub fn pixel_to_point(&self, x: usize, y: usize, x_res: usize, y_res: usize) -> (f64, f64) {
+        let scale = 2.0 * self.domain.half_extent / x_res.min(y_res) as f64;
+        let fx = self.domain.center_x + (x as f64 + 0.5 - x_res as f64 / 2.0) * scale;
+        let fy = self.domain.center_y + (y as f64 + 0.5 - y_res as f64 / 2.0) * scale;
+        (fx, fy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close((x, y): (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (x - expected.0).abs() < 1e-12 && (y - expected.1).abs() < 1e-12,
+            "({}, {}) != {:?}",
+            x,
+            y,
+            expected
+        );
+    }
+
+    #[test]
+    fn wide_images_extend_the_domain_horizontally() {
+        let spec = RenderSpec::default();
+        let point = |x, y| spec.pixel_to_point(x, y, 1600, 800);
+        // Pixel centers are half a pixel, 1/800, inside [-2, 2] × [-1, 1]
+        let half = 0.5 * 2.0 / 800.0;
+        assert_close(point(0, 0), (-2.0 + half, -1.0 + half));
+        assert_close(point(1599, 799), (2.0 - half, 1.0 - half));
+        assert_close(point(800, 400), (half, half));
+        // Pixels are square
+        assert_close(point(1, 1), (-2.0 + 3.0 * half, -1.0 + 3.0 * half));
+    }
+
+    #[test]
+    fn points_follow_the_domain() {
+        let spec = RenderSpec {
+            domain: Domain {
+                center_x: 3.0,
+                center_y: -1.0,
+                half_extent: 0.5,
+            },
+            ..RenderSpec::default()
+        };
+        let point = |x, y| spec.pixel_to_point(x, y, 200, 400);
+        assert_close(point(0, 0), (2.5 + 0.0025, -2.0 + 0.0025));
+        assert_close(point(199, 399), (3.5 - 0.0025, 0.0 - 0.0025));
+    }
+}
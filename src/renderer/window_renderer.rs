@@ -1,13 +1,171 @@
+use crate::images::ImageSet;
 use crate::operations::Operation;
+use crate::renderer::{FileRenderer, RenderSpec, TimeMapping};
+use crate::seed::{Seed, SeedError};
+use crate::shader::{Glsl330, ShaderBackend};
 use macroquad::camera::set_default_camera;
-use macroquad::color::WHITE;
-use macroquad::material::{gl_use_material, load_material, MaterialParams};
-use macroquad::miniquad::window::set_window_size;
-use macroquad::miniquad::{Comparison, PipelineParams, ShaderSource, UniformDesc, UniformType};
-use macroquad::prelude::{clear_background, draw_rectangle, get_time, next_frame};
-use std::process::exit;
+use macroquad::color::{Color, WHITE};
+use macroquad::input::{
+    is_key_down, is_key_pressed, is_mouse_button_down, mouse_position, mouse_wheel, KeyCode,
+    MouseButton,
+};
+use macroquad::material::{
+    gl_use_default_material, gl_use_material, load_material, Material, MaterialParams,
+};
+use macroquad::miniquad::{
+    Comparison, PipelineParams, ShaderError, ShaderSource, UniformDesc, UniformType,
+};
+use macroquad::prelude::{
+    clear_background, draw_rectangle, draw_text, get_fps, get_frame_time, get_time, next_frame,
+    screen_height, screen_width,
+};
+use macroquad::texture::Texture2D;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use thiserror::Error;
 
-pub struct WindowRenderer {}
+/// Seconds of time scrubbed per second while an arrow key is held.
+const SCRUB_SPEED: f64 = 2.0;
+
+/// Factor by which one step of the mouse wheel zooms.
+const ZOOM_STEP: f64 = 1.25;
+
+/// Seconds between checks of a watched file.
+const WATCH_INTERVAL: f64 = 0.25;
+
+/// Characters of the tree shown by the HUD, in lines of `HUD_LINE_CHARS`.
+const HUD_TREE_CHARS: usize = 240;
+const HUD_LINE_CHARS: usize = 80;
+const HUD_FONT_SIZE: f32 = 20.0;
+const HUD_LINE_HEIGHT: f32 = 18.0;
+const HUD_MARGIN: f32 = 8.0;
+
+#[derive(Error, Debug)]
+pub enum WindowRenderError {
+    #[error("Failed to compile shader: {0}")]
+    ShaderError(String),
+
+    #[error("Failed to load material: {0}")]
+    MaterialError(macroquad::Error),
+}
+
+impl From<macroquad::Error> for WindowRenderError {
+    fn from(err: macroquad::Error) -> Self {
+        match err {
+            macroquad::Error::ShaderError(ShaderError::CompilationError {
+                error_message, ..
+            }) => WindowRenderError::ShaderError(error_message),
+            macroquad::Error::ShaderError(ShaderError::LinkError(message)) => {
+                WindowRenderError::ShaderError(message)
+            }
+            err => WindowRenderError::MaterialError(err),
+        }
+    }
+}
+
+/// The seed, depth and grammar a tree is generated from, which the window
+/// controls can change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeParams {
+    /// Seed string, as passed to `--seed`.
+    pub seed: String,
+    /// Whether `seed` is hex-encoded bytes.
+    pub hex_seed: bool,
+    pub depth: usize,
+    pub alternative_grammar: bool,
+}
+
+impl TreeParams {
+    pub fn seed(&self) -> Result<Seed, SeedError> {
+        if self.hex_seed {
+            Seed::from_hex(&self.seed)
+        } else {
+            Ok(Seed::from_string(&self.seed))
+        }
+    }
+}
+
+type GenerateFn = Box<dyn Fn(&TreeParams) -> Result<Operation, String>>;
+type CommandLineFn = Box<dyn Fn(Option<&TreeParams>) -> String>;
+type LoadFn = Box<dyn Fn(&Path) -> Result<Operation, String>>;
+
+/// Generates the trees the window switches between. Stepping through seeds
+/// derives the seed strings `<seed>/1`, `<seed>/2`, ... from the first one.
+struct Generator {
+    base: TreeParams,
+    seed_index: i64,
+    params: TreeParams,
+    generate: GenerateFn,
+}
+
+impl Generator {
+    fn seed_params(&self, seed_index: i64) -> (String, bool) {
+        if seed_index == 0 {
+            (self.base.seed.clone(), self.base.hex_seed)
+        } else {
+            (format!("{}/{}", self.base.seed, seed_index), false)
+        }
+    }
+}
+
+/// Reloads the tree from a file whenever its modification time changes.
+struct Watch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: f64,
+    load: LoadFn,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Renders a tree with a shader in a window.
+///
+/// Controls:
+/// - `N` / `P`: next / previous seed, `Up` / `Down`: increase / decrease the
+///   depth, `G`: toggle the grammar (all need [`WindowRenderer::with_generator`])
+/// - `Space`: pause or resume, `Left` / `Right` (held): scrub time
+/// - drag: pan, mouse wheel: zoom, `R`: reset the view
+/// - `S`: save the current view through [`FileRenderer`]
+/// - `H`: show or hide the HUD, `C`: print the command line reproducing the
+///   tree (needs [`WindowRenderer::with_command_line`])
+pub struct WindowRenderer {
+    spec: RenderSpec,
+    images: ImageSet,
+    generator: Option<Generator>,
+    watch: Option<Watch>,
+    output_path: String,
+    save_scale: usize,
+    hud: bool,
+    command_line: Option<CommandLineFn>,
+}
+
+/// What the HUD shows about a tree, computed when the tree changes.
+struct TreeSummary {
+    node_count: usize,
+    lines: Vec<String>,
+}
+
+impl TreeSummary {
+    fn new(root: &Operation) -> Self {
+        let mut text: Vec<char> = root.to_string().chars().collect();
+        if text.len() > HUD_TREE_CHARS {
+            text.truncate(HUD_TREE_CHARS - 3);
+            text.extend("...".chars());
+        }
+        TreeSummary {
+            node_count: root.node_count(),
+            lines: text
+                .chunks(HUD_LINE_CHARS)
+                .map(|line| line.iter().collect())
+                .collect(),
+        }
+    }
+}
 
 impl Default for WindowRenderer {
     fn default() -> Self {
@@ -17,41 +175,212 @@ impl Default for WindowRenderer {
 
 impl WindowRenderer {
     pub fn new() -> Self {
-        WindowRenderer {}
+        WindowRenderer {
+            spec: RenderSpec::default(),
+            images: ImageSet::default(),
+            generator: None,
+            watch: None,
+            output_path: "generated/random_art.png".to_string(),
+            save_scale: 4,
+            hud: false,
+            command_line: None,
+        }
+    }
+
+    pub fn with_spec(mut self, spec: RenderSpec) -> Self {
+        self.spec = spec;
+        self
+    }
+
+    /// Sets the images sampled by image leaves, bound as textures `image_<id>`.
+    pub fn with_images(mut self, images: ImageSet) -> Self {
+        self.images = images;
+        self
     }
-    pub async fn render(&mut self, x_res: usize, y_res: usize, root: &Operation) {
+
+    /// Lets the seed, depth and grammar controls replace the tree with
+    /// `generate(params)`, starting from the `params` of the rendered tree.
+    /// Trees that fail to generate are reported and the current one is kept.
+    pub fn with_generator(
+        mut self,
+        params: TreeParams,
+        generate: impl Fn(&TreeParams) -> Result<Operation, String> + 'static,
+    ) -> Self {
+        self.generator = Some(Generator {
+            base: params.clone(),
+            seed_index: 0,
+            params,
+            generate: Box::new(generate),
+        });
+        self
+    }
+
+    /// Replaces the tree with `load(path)` whenever the file at `path`
+    /// changes. Trees that fail to load are reported and the last good one is
+    /// kept.
+    pub fn with_watch(
+        mut self,
+        path: impl Into<PathBuf>,
+        load: impl Fn(&Path) -> Result<Operation, String> + 'static,
+    ) -> Self {
+        let path = path.into();
+        self.watch = Some(Watch {
+            modified: modified(&path),
+            path,
+            last_check: 0.0,
+            load: Box::new(load),
+        });
+        self
+    }
+
+    /// Sets the path saved views are numbered after, e.g. `art.png` saves
+    /// `art_1.png`, `art_2.png`, ...
+    pub fn with_output(mut self, output_path: String) -> Self {
+        self.output_path = output_path;
+        self
+    }
+
+    /// Sets how many times larger than the window saved views are.
+    pub fn with_save_scale(mut self, save_scale: usize) -> Self {
+        self.save_scale = save_scale.max(1);
+        self
+    }
+
+    /// Shows the HUD with the seed, depth, grammar, node count, frame rate and
+    /// the beginning of the tree from the start.
+    pub fn with_hud(mut self, hud: bool) -> Self {
+        self.hud = hud;
+        self
+    }
+
+    /// Lets the `C` key print `command_line(params)`, the command reproducing
+    /// the tree generated from `params`, or the rendered tree if it was not
+    /// generated by [`WindowRenderer::with_generator`].
+    pub fn with_command_line(
+        mut self,
+        command_line: impl Fn(Option<&TreeParams>) -> String + 'static,
+    ) -> Self {
+        self.command_line = Some(Box::new(command_line));
+        self
+    }
+
+    /// Renders `root` until the window is closed. The resolution follows the
+    /// size of the window, which is set when it is created.
+    pub async fn render(&mut self, root: &Operation) -> Result<(), WindowRenderError> {
+        let textures: Vec<Texture2D> = self
+            .images
+            .images()
+            .iter()
+            .map(|input| {
+                let image = input.image();
+                let rgba: Vec<u8> = image
+                    .pixels()
+                    .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+                    .collect();
+                Texture2D::from_rgba8(image.width() as u16, image.height() as u16, &rgba)
+            })
+            .collect();
+        let mut root = root.clone();
+        let mut material = self.load_material(&root, &textures)?;
+        let mut summary = TreeSummary::new(&root);
+        let initial_domain = self.spec.domain;
+        let mut time = 0.0;
+        let mut paused = false;
+        let mut drag: Option<(f32, f32)> = None;
         loop {
-            clear_background(WHITE);
-            set_window_size(x_res as u32, y_res as u32);
-            let vertex_shader = DEFAULT_VERTEX_SHADER.to_string();
-            let pipeline_params = PipelineParams {
-                depth_write: true,
-                depth_test: Comparison::LessOrEqual,
-                ..Default::default()
+            let (tree, generated) = match self.update_generator() {
+                Some((tree, seed_index, params)) => (Some(tree), Some((seed_index, params))),
+                None => (self.update_watch(), None),
             };
-            let glsl = root.to_glsl();
-            let fragment_shader = DEFAULT_FRAGMENT_SHADER.replace("#REPLACE_ME#", &glsl);
-            let uniforms = vec![UniformDesc::new("time", UniformType::Float1)];
-
-            let material = match load_material(
-                ShaderSource::Glsl {
-                    vertex: &vertex_shader,
-                    fragment: &fragment_shader,
-                },
-                MaterialParams {
-                    pipeline_params,
-                    uniforms,
-                    ..Default::default()
-                },
-            ) {
-                Ok(mat) => mat,
-                Err(err) => {
-                    eprintln!("Error loading material: {:?}", err);
-                    exit(1);
+            if let Some(tree) = tree {
+                // Keep showing the previous tree if the new shader fails
+                match self.load_material(&tree, &textures) {
+                    Ok(loaded) => {
+                        if let (Some(generator), Some((seed_index, params))) =
+                            (self.generator.as_mut(), generated)
+                        {
+                            generator.seed_index = seed_index;
+                            generator.params = params;
+                        }
+                        material = loaded;
+                        summary = TreeSummary::new(&tree);
+                        root = tree;
+                    }
+                    Err(err) => eprintln!("{}", err),
                 }
-            };
+            }
+
+            if is_key_pressed(KeyCode::Space) {
+                paused = !paused;
+            }
+            let frame_time = get_frame_time() as f64;
+            if !paused {
+                time += frame_time;
+            }
+            if is_key_down(KeyCode::Right) {
+                time += SCRUB_SPEED * frame_time;
+            }
+            if is_key_down(KeyCode::Left) {
+                time -= SCRUB_SPEED * frame_time;
+            }
 
-            material.set_uniform("time", get_time() as f32);
+            let (width, height) = (screen_width(), screen_height());
+            let domain = &mut self.spec.domain;
+            let scale = 2.0 * domain.half_extent / width.min(height) as f64;
+            let (mouse_x, mouse_y) = mouse_position();
+            if is_mouse_button_down(MouseButton::Left) {
+                if let Some((last_x, last_y)) = drag {
+                    domain.center_x -= (mouse_x - last_x) as f64 * scale;
+                    domain.center_y -= (mouse_y - last_y) as f64 * scale;
+                }
+                drag = Some((mouse_x, mouse_y));
+            } else {
+                drag = None;
+            }
+            let (_, wheel) = mouse_wheel();
+            if wheel != 0.0 {
+                // Keep the point under the cursor in place
+                let factor = if wheel > 0.0 {
+                    1.0 / ZOOM_STEP
+                } else {
+                    ZOOM_STEP
+                };
+                let x = domain.center_x + (mouse_x - width / 2.0) as f64 * scale;
+                let y = domain.center_y + (mouse_y - height / 2.0) as f64 * scale;
+                domain.center_x = x - (x - domain.center_x) * factor;
+                domain.center_y = y - (y - domain.center_y) * factor;
+                domain.half_extent *= factor;
+            }
+            if is_key_pressed(KeyCode::R) {
+                *domain = initial_domain;
+            }
+
+            if is_key_pressed(KeyCode::S) {
+                self.save(&root, time, width as usize, height as usize);
+            }
+            if is_key_pressed(KeyCode::H) {
+                self.hud = !self.hud;
+            }
+            if is_key_pressed(KeyCode::C) {
+                if let Some(command_line) = &self.command_line {
+                    let params = self.generator.as_ref().map(|generator| &generator.params);
+                    println!("{}", command_line(params));
+                }
+            }
+
+            clear_background(WHITE);
+
+            material.set_uniform("time", time as f32);
+            material.set_uniform("resolution", (width, height));
+            let domain = self.spec.domain;
+            material.set_uniform(
+                "domain",
+                (
+                    domain.center_x as f32,
+                    domain.center_y as f32,
+                    domain.half_extent as f32,
+                ),
+            );
 
             gl_use_material(&material);
 
@@ -59,38 +388,192 @@ impl WindowRenderer {
 
             set_default_camera();
 
-            material.set_uniform("time", get_time() as f32);
+            if self.hud {
+                gl_use_default_material();
+                self.draw_hud(&summary, time, paused);
+            }
 
             next_frame().await
         }
     }
-}
 
-const DEFAULT_FRAGMENT_SHADER: &str = r#"
-#version 330
-in vec2 fragTexCoord;
-out vec4 finalColor;
-uniform float time;
+    /// Applies the seed, depth and grammar controls, returning the new tree
+    /// and the seed index and parameters it was generated from if they
+    /// changed. The caller applies them once the tree's shader loads.
+    fn update_generator(&self) -> Option<(Operation, i64, TreeParams)> {
+        let generator = self.generator.as_ref()?;
+        let mut seed_index = generator.seed_index;
+        let mut params = generator.params.clone();
+        if is_key_pressed(KeyCode::N) {
+            seed_index += 1;
+        }
+        if is_key_pressed(KeyCode::P) {
+            seed_index -= 1;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            params.depth += 1;
+        }
+        if is_key_pressed(KeyCode::Down) {
+            params.depth = params.depth.saturating_sub(1).max(1);
+        }
+        if is_key_pressed(KeyCode::G) {
+            params.alternative_grammar = !params.alternative_grammar;
+        }
+        (params.seed, params.hex_seed) = generator.seed_params(seed_index);
+        if params == generator.params {
+            return None;
+        }
+        match (generator.generate)(&params) {
+            Ok(tree) => Some((tree, seed_index, params)),
+            Err(err) => {
+                eprintln!("{}", err);
+                None
+            }
+        }
+    }
 
-vec4 map_color(vec3 rgb) {
-    return vec4((rgb +1)/2.0 , 1.0);
-}
+    /// Reloads the watched file if it changed, returning the new tree if it
+    /// loaded.
+    fn update_watch(&mut self) -> Option<Operation> {
+        let watch = self.watch.as_mut()?;
+        let now = get_time();
+        if now - watch.last_check < WATCH_INTERVAL {
+            return None;
+        }
+        watch.last_check = now;
+        let modified = modified(&watch.path);
+        if modified == watch.modified {
+            return None;
+        }
+        watch.modified = modified;
+        match (watch.load)(&watch.path) {
+            Ok(tree) => {
+                println!("Reloaded {}", watch.path.display());
+                Some(tree)
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                None
+            }
+        }
+    }
 
-float well_fn(float x) {
-    return pow(1.0 - 2.0 / (1.0 + x * x), 8);
-}
+    fn draw_hud(&self, summary: &TreeSummary, time: f64, paused: bool) {
+        let mut lines = Vec::new();
+        if let Some(generator) = &self.generator {
+            let params = &generator.params;
+            lines.push(format!(
+                "seed: {}{}   depth: {}   grammar: {}",
+                params.seed,
+                if params.hex_seed { " (hex)" } else { "" },
+                params.depth,
+                if params.alternative_grammar {
+                    "alternative"
+                } else {
+                    "default"
+                }
+            ));
+        }
+        lines.push(format!(
+            "nodes: {}   fps: {}   t: {:.3}{}",
+            summary.node_count,
+            get_fps(),
+            self.spec.time.t(time),
+            if paused { " (paused)" } else { "" }
+        ));
+        lines.extend(summary.lines.iter().cloned());
 
-float tent_fn(float x) {
-    return 1.0 - 2.0 * abs(x);
+        let height = lines.len() as f32 * HUD_LINE_HEIGHT + 2.0 * HUD_MARGIN;
+        draw_rectangle(
+            0.0,
+            0.0,
+            screen_width(),
+            height,
+            Color::new(0.0, 0.0, 0.0, 0.6),
+        );
+        for (i, line) in lines.iter().enumerate() {
+            let baseline = HUD_MARGIN + (i as f32 + 0.8) * HUD_LINE_HEIGHT;
+            draw_text(line, HUD_MARGIN, baseline, HUD_FONT_SIZE, WHITE);
+        }
+    }
+
+    /// Renders the current view at `save_scale` times the window size to the
+    /// next free numbered output path.
+    fn save(&self, root: &Operation, time: f64, width: usize, height: usize) {
+        let path = next_output_path(&self.output_path);
+        let (x_res, y_res) = (width * self.save_scale, height * self.save_scale);
+        println!("Saving {}x{} image to {}", x_res, y_res, path);
+        let spec = RenderSpec {
+            time: TimeMapping::Fixed(self.spec.time.t(time)),
+            ..self.spec
+        };
+        if let Err(err) = FileRenderer::new(path.clone())
+            .with_spec(spec)
+            .with_images(self.images.clone())
+            .render(x_res, y_res, root)
+        {
+            eprintln!("{}: {}", path, err);
+        }
+    }
+
+    /// Compiles the shader rendering `root`, with image `id` bound to
+    /// `textures[id]`. Only needs to be called again when the tree changes.
+    fn load_material(
+        &self,
+        root: &Operation,
+        textures: &[Texture2D],
+    ) -> Result<Material, WindowRenderError> {
+        let texture_names: Vec<String> = (0..textures.len())
+            .map(|id| format!("image_{}", id))
+            .collect();
+        let pipeline_params = PipelineParams {
+            depth_write: true,
+            depth_test: Comparison::LessOrEqual,
+            ..Default::default()
+        };
+        let fragment_shader = Glsl330.fragment_shader(root, &self.spec, &self.images);
+        let uniforms = vec![
+            UniformDesc::new("time", UniformType::Float1),
+            UniformDesc::new("resolution", UniformType::Float2),
+            UniformDesc::new("domain", UniformType::Float3),
+        ];
+
+        let material = load_material(
+            ShaderSource::Glsl {
+                vertex: DEFAULT_VERTEX_SHADER,
+                fragment: &fragment_shader,
+            },
+            MaterialParams {
+                pipeline_params,
+                uniforms,
+                textures: texture_names.clone(),
+            },
+        )?;
+        for (name, texture) in texture_names.iter().zip(textures) {
+            material.set_texture(name, texture.clone());
+        }
+        Ok(material)
+    }
 }
 
-void main() {
-    float x = fragTexCoord.x * 2.0 - 1.0;
-    float y = fragTexCoord.y * 2.0 - 1.0;
-    float t = sin(time);
-    finalColor = map_color(#REPLACE_ME#);
+/// The first of `<stem>_1.<ext>`, `<stem>_2.<ext>`, ... that does not exist.
+fn next_output_path(output_path: &str) -> String {
+    let path = Path::new(output_path);
+    let stem = path
+        .file_stem()
+        .map_or("random_art".into(), |stem| stem.to_string_lossy());
+    let extension = path
+        .extension()
+        .map_or("png".into(), |extension| extension.to_string_lossy());
+    (1..)
+        .map(|n| {
+            path.with_file_name(format!("{}_{}.{}", stem, n, extension))
+                .to_string_lossy()
+                .into_owned()
+        })
+        .find(|candidate| !Path::new(candidate).exists())
+        .expect("Ran out of file names")
 }
-"#;
 
 const DEFAULT_VERTEX_SHADER: &str = r#"
 #version 330 core
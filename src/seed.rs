@@ -0,0 +1,205 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SeedError {
+    #[error("Hex seed has an odd number of digits")]
+    OddLength,
+
+    #[error("Invalid hex digit {0:?} at position {1}")]
+    InvalidHexDigit(char, usize),
+}
+
+/// Seed of the random number generator used by the grammars.
+///
+/// A seed is the 32-byte key of a ChaCha12 generator. Strings and byte
+/// sequences are reduced to a key with SHA-256, so a seed string always
+/// produces the same tree, independently of the Rust toolchain:
+///
+/// `key = SHA-256(bytes)`, where `bytes` is the UTF-8 encoding of a seed
+/// string or the decoded bytes of a hex seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Seed([u8; 32]);
+
+impl Seed {
+    /// Uses `key` as the generator key without hashing it.
+    pub fn from_key(key: [u8; 32]) -> Self {
+        Seed(key)
+    }
+
+    /// Uses the bits of `digest` directly as the generator key, without
+    /// hashing them again. Shorter digests are zero-padded, so a digest and
+    /// the same digest followed by zero bytes give the same key. Longer ones
+    /// are folded into 32 bytes with XOR: every bit of the digest affects the
+    /// key, but different long digests can give the same one.
+    pub fn from_digest(digest: &[u8]) -> Self {
+        let mut key = [0u8; 32];
+        for (i, byte) in digest.iter().enumerate() {
+            key[i % 32] ^= byte;
+        }
+        Seed(key)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Seed(Sha256::digest(bytes).into())
+    }
+
+    pub fn from_string(seed: &str) -> Self {
+        Self::from_bytes(seed.as_bytes())
+    }
+
+    /// Decodes `hex` and derives the seed from the resulting bytes, so a hex
+    /// digest seeds the same tree as its raw bytes passed to [`Seed::from_bytes`].
+    pub fn from_hex(hex: &str) -> Result<Self, SeedError> {
+        Ok(Self::from_bytes(&decode_hex(hex)?))
+    }
+
+    pub fn key(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn rng(&self) -> ChaCha12Rng {
+        ChaCha12Rng::from_seed(self.0)
+    }
+}
+
+impl Display for Seed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a string of hex digits, ignoring `:` separators as used in key fingerprints.
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>, SeedError> {
+    let digits = hex
+        .char_indices()
+        .filter(|(_, c)| *c != ':')
+        .map(|(i, c)| {
+            c.to_digit(16)
+                .map(|d| d as u8)
+                .ok_or(SeedError::InvalidHexDigit(c, i))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if digits.len() % 2 != 0 {
+        return Err(SeedError::OddLength);
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::{ArtGrammar, PerrigSongGrammar, RandomArtGrammar};
+
+    // Changing any of these breaks every seed users have archived.
+
+    fn perrig_song(seed: &str, depth: usize) -> String {
+        PerrigSongGrammar::from_seed(&Seed::from_string(seed))
+            .generate_tree(depth)
+            .to_string()
+    }
+
+    fn random_art(seed: &str, depth: usize) -> String {
+        RandomArtGrammar::from_seed(&Seed::from_string(seed))
+            .generate_tree(depth)
+            .to_string()
+    }
+
+    #[test]
+    fn perrig_song_trees_are_stable() {
+        assert_eq!(
+            perrig_song("", 2),
+            concat!(
+                "rgb(((0.0531366058543663 * x) + (-0.19007977497916007 + t)), ((x + x) * t), ",
+                "((0.2186227929892277 + t) * (y * 0.8152320984720292)))"
+            )
+        );
+        assert_eq!(
+            perrig_song("hello", 3),
+            concat!(
+                "rgb((((x * y) * (y + y)) + ((y + x) * (y + t))), ",
+                "((-0.5390069171185957 * (0.4227718521148145 + t)) + ",
+                "((y * -0.8831612898655217) + (x + y))), ",
+                "(((x + x) * -0.22281563772470458) * (y * (-0.6073678153836392 * x))))"
+            )
+        );
+        assert_eq!(
+            perrig_song("random art", 4),
+            concat!(
+                "rgb(y, ((((t + t) * (t + t)) + t) * ",
+                "(((0.07394277175558073 * 0.2060982835542473) * ",
+                "(0.21866541042473853 * 0.14810963006045852)) + ",
+                "((t * t) + (-0.5330619058868228 + y)))), ",
+                "(((y * t) * ((x + 0.2131925266584347) * (y + y))) * ",
+                "((y + (0.8557393639818063 * y)) + ((t + t) * (-0.4142167759517531 + x)))))"
+            )
+        );
+    }
+
+    #[test]
+    fn random_art_trees_are_stable() {
+        assert_eq!(random_art("", 2), "(sin(y) * tent(x))");
+        assert_eq!(random_art("hello", 3), "-(-y * -x)");
+        assert_eq!(
+            random_art("random art", 4),
+            concat!(
+                "(smooth_mix(tent((circle(-0.8204672752153868, -0.17829330369683372) + y)), ",
+                "tent((circle(0.42354734717485276, -0.5133044017865384) * t)), ",
+                "binary_mask(-x, binary_mask(0.5758524464113535, -0.1258343203999106, ",
+                "circle(0.46976567903794275, -0.5844361530364964), 0.6134626403330974), ",
+                "(t % x), -0.6756380638036883)) * ",
+                "-sin(binary_mask(x, t, circle(0.4505581522748503, -0.6964067265765053), ",
+                "0.5024424860304997)))"
+            )
+        );
+    }
+
+    #[test]
+    fn string_seeds_hash_with_sha256() {
+        assert_eq!(
+            Seed::from_string("hello").to_string(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn hex_seeds_hash_the_decoded_bytes() {
+        let seed = Seed::from_hex("00:ff:10").unwrap();
+        assert_eq!(seed, Seed::from_bytes(&[0x00, 0xff, 0x10]));
+        assert_eq!(
+            seed.to_string(),
+            "2da45f2cd1f9c8e69a67abf7a6b26c282533d0a7686787a9533265418680d4d2"
+        );
+        assert_eq!(Seed::from_hex("abc"), Err(SeedError::OddLength));
+        assert_eq!(
+            Seed::from_hex("0g"),
+            Err(SeedError::InvalidHexDigit('g', 1))
+        );
+    }
+
+    #[test]
+    fn digests_are_used_without_hashing() {
+        assert_eq!(
+            Seed::from_digest(&[1, 2, 3]).to_string(),
+            "0102030000000000000000000000000000000000000000000000000000000000"
+        );
+        let long_digest: Vec<u8> = (0..40).collect();
+        assert_eq!(
+            Seed::from_digest(&long_digest).to_string(),
+            "202020202020202008090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+        );
+        assert_eq!(
+            Seed::from_digest(&[1, 2, 3]),
+            Seed::from_digest(&[1, 2, 3, 0])
+        );
+    }
+}
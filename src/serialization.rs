@@ -0,0 +1,163 @@
+use crate::operations::Operation;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+/// Version of the tree file schema written by [`save_tree`].
+///
+/// Bump this whenever the serialized shape of [`Operation`] changes, and keep
+/// [`load_tree`] able to read every older version.
+pub const TREE_FORMAT_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum TreeFileError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("RON error: {0}")]
+    Ron(#[from] ron::Error),
+
+    #[error("RON error: {0}")]
+    RonSpanned(#[from] ron::error::SpannedError),
+
+    #[error("Unsupported tree format version {0} (latest supported is {TREE_FORMAT_VERSION})")]
+    UnsupportedVersion(u32),
+
+    #[error("Unknown tree file extension for {0} (expected .json or .ron)")]
+    UnknownFormat(String),
+
+    #[error("JSON cannot represent infinite or NaN parameters; save the tree as .ron instead")]
+    NonFiniteJson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeFormat {
+    Json,
+    Ron,
+}
+
+impl TreeFormat {
+    pub fn from_path(path: &Path) -> Result<Self, TreeFileError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(TreeFormat::Json),
+            Some("ron") => Ok(TreeFormat::Ron),
+            _ => Err(TreeFileError::UnknownFormat(path.display().to_string())),
+        }
+    }
+}
+
+/// On-disk representation of an expression tree.
+///
+/// Floating point values are written with enough precision to be read back
+/// bit-exactly, so a loaded tree renders the same image as the original.
+/// JSON cannot represent non-finite constants, so writing such trees as JSON
+/// fails; use RON for them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TreeFile {
+    pub version: u32,
+    pub tree: Operation,
+}
+
+impl TreeFile {
+    pub fn new(tree: Operation) -> Self {
+        TreeFile {
+            version: TREE_FORMAT_VERSION,
+            tree,
+        }
+    }
+
+    pub fn to_string(&self, format: TreeFormat) -> Result<String, TreeFileError> {
+        match format {
+            TreeFormat::Json => {
+                if contains_null(&serde_json::to_value(self)?) {
+                    return Err(TreeFileError::NonFiniteJson);
+                }
+                Ok(serde_json::to_string_pretty(self)?)
+            }
+            TreeFormat::Ron => Ok(ron::ser::to_string_pretty(
+                self,
+                ron::ser::PrettyConfig::default(),
+            )?),
+        }
+    }
+
+    pub fn from_str(source: &str, format: TreeFormat) -> Result<Self, TreeFileError> {
+        let file: TreeFile = match format {
+            TreeFormat::Json => serde_json::from_str(source)?,
+            TreeFormat::Ron => ron::from_str(source)?,
+        };
+        if file.version > TREE_FORMAT_VERSION {
+            return Err(TreeFileError::UnsupportedVersion(file.version));
+        }
+        Ok(file)
+    }
+}
+
+/// Whether `value` contains a `null`, which is how serde_json writes
+/// infinite and NaN numbers; trees have no other optional values.
+fn contains_null(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Null => true,
+        serde_json::Value::Array(items) => items.iter().any(contains_null),
+        serde_json::Value::Object(fields) => fields.values().any(contains_null),
+        _ => false,
+    }
+}
+
+/// Saves a tree to `path`, choosing JSON or RON from the file extension.
+/// Trees with infinite or NaN parameters can only be saved as RON.
+pub fn save_tree(path: impl AsRef<Path>, tree: &Operation) -> Result<(), TreeFileError> {
+    let path = path.as_ref();
+    let format = TreeFormat::from_path(path)?;
+    fs::write(path, TreeFile::new(tree.clone()).to_string(format)?)?;
+    Ok(())
+}
+
+/// Loads a tree saved by [`save_tree`], choosing JSON or RON from the file extension.
+pub fn load_tree(path: impl AsRef<Path>) -> Result<Operation, TreeFileError> {
+    let path = path.as_ref();
+    let format = TreeFormat::from_path(path)?;
+    let source = fs::read_to_string(path)?;
+    Ok(TreeFile::from_str(&source, format)?.tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_rejects_non_finite_parameters() {
+        let tree = Operation::Sum(
+            Operation::VarX.into(),
+            Operation::Circle(f64::INFINITY, f64::NAN).into(),
+        );
+        let file = TreeFile::new(tree);
+        assert!(matches!(
+            file.to_string(TreeFormat::Json),
+            Err(TreeFileError::NonFiniteJson)
+        ));
+        let ron = file.to_string(TreeFormat::Ron).unwrap();
+        let loaded = TreeFile::from_str(&ron, TreeFormat::Ron).unwrap();
+        assert_eq!(format!("{:?}", loaded.tree), format!("{:?}", file.tree));
+    }
+
+    #[test]
+    fn json_round_trips_finite_trees() {
+        let tree = Operation::BinaryMask(
+            Operation::Constant(0.1 + 0.2).into(),
+            Operation::VarX.into(),
+            Operation::Circle(-0.75, 1e300).into(),
+            1.0 / 3.0,
+        );
+        let json = TreeFile::new(tree.clone())
+            .to_string(TreeFormat::Json)
+            .unwrap();
+        let loaded = TreeFile::from_str(&json, TreeFormat::Json).unwrap().tree;
+        // Debug prints the shortest representation that reads back exactly
+        assert_eq!(format!("{:?}", loaded), format!("{:?}", tree));
+    }
+}
@@ -0,0 +1,384 @@
+use crate::images::ImageSet;
+use crate::noise;
+use crate::operations::Operation;
+use crate::renderer::RenderSpec;
+use crate::shader::{float_literal, tree_functions, ShaderBackend};
+
+/// GLSL 330, as used by the window renderer. The pixel position comes from
+/// a `fragTexCoord` input spanning [0, 1]² from the bottom left corner.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Glsl330;
+
+/// GLSL ES 3.0, for WebGL 2. The pixel position comes from `gl_FragCoord`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlslEs300;
+
+/// GLSL for Shadertoy: a `mainImage` function using the `iTime` and
+/// `iResolution` inputs, with the domain of the spec built in. Image leaves
+/// sample the textures bound to `iChannel0` to `iChannel3`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Shadertoy;
+
+impl ShaderBackend for Glsl330 {
+    fn fragment_shader(&self, root: &Operation, spec: &RenderSpec, images: &ImageSet) -> String {
+        fragment_shader(
+            self,
+            "#version 330\nin vec2 fragTexCoord;\n",
+            // Texture coordinates start at the bottom, while image rows start at the top
+            "vec2(fragTexCoord.x, 1.0 - fragTexCoord.y) * resolution",
+            root,
+            spec,
+            images,
+        )
+    }
+
+    fn expression(&self, op: &Operation, children: &[String], function: Option<&str>) -> String {
+        expression(op, children, function)
+    }
+
+    fn variable(&self, name: &str, expression: &str) -> String {
+        variable(name, expression)
+    }
+
+    fn function(&self, name: &str, statements: &str, result: &str) -> String {
+        function(name, statements, result)
+    }
+}
+
+impl ShaderBackend for GlslEs300 {
+    fn fragment_shader(&self, root: &Operation, spec: &RenderSpec, images: &ImageSet) -> String {
+        fragment_shader(
+            self,
+            "#version 300 es\nprecision highp float;\nprecision highp int;\n",
+            // Fragment coordinates start at the bottom, while image rows start at the top
+            "vec2(gl_FragCoord.x, resolution.y - gl_FragCoord.y)",
+            root,
+            spec,
+            images,
+        )
+    }
+
+    fn expression(&self, op: &Operation, children: &[String], function: Option<&str>) -> String {
+        expression(op, children, function)
+    }
+
+    fn variable(&self, name: &str, expression: &str) -> String {
+        variable(name, expression)
+    }
+
+    fn function(&self, name: &str, statements: &str, result: &str) -> String {
+        function(name, statements, result)
+    }
+}
+
+impl ShaderBackend for Shadertoy {
+    fn fragment_shader(&self, root: &Operation, spec: &RenderSpec, images: &ImageSet) -> String {
+        let (name, functions) = tree_functions(self, root);
+        let domain = spec.domain;
+        SHADERTOY_SHADER
+            .replace(
+                "#DOMAIN#",
+                &format!(
+                    "vec3({}, {}, {})",
+                    float_literal(domain.center_x),
+                    float_literal(domain.center_y),
+                    float_literal(domain.half_extent)
+                ),
+            )
+            .replace("#MAP_COLOR#", spec.color.to_glsl())
+            .replace("#HELPERS#", HELPERS)
+            .replace("#NOISE_FUNCTIONS#", noise::GLSL_FUNCTIONS)
+            .replace("#IMAGE_FUNCTIONS#", &images.shadertoy_functions(root))
+            .replace("#FUNCTIONS#", &functions)
+            .replace("#TIME#", &spec.time.to_glsl())
+            .replace("#ROOT#", &name)
+    }
+
+    fn expression(&self, op: &Operation, children: &[String], function: Option<&str>) -> String {
+        expression(op, children, function)
+    }
+
+    fn variable(&self, name: &str, expression: &str) -> String {
+        variable(name, expression)
+    }
+
+    fn function(&self, name: &str, statements: &str, result: &str) -> String {
+        function(name, statements, result)
+    }
+}
+
+fn fragment_shader(
+    backend: &dyn ShaderBackend,
+    header: &str,
+    pixel: &str,
+    root: &Operation,
+    spec: &RenderSpec,
+    images: &ImageSet,
+) -> String {
+    let (name, functions) = tree_functions(backend, root);
+    FRAGMENT_SHADER
+        .replace("#HEADER#", header)
+        .replace("#MAP_COLOR#", spec.color.to_glsl())
+        .replace("#HELPERS#", HELPERS)
+        .replace("#NOISE_FUNCTIONS#", noise::GLSL_FUNCTIONS)
+        .replace("#IMAGE_FUNCTIONS#", &images.glsl_functions(root))
+        .replace("#FUNCTIONS#", &functions)
+        .replace("#PIXEL#", pixel)
+        .replace("#TIME#", &spec.time.to_glsl())
+        .replace("#ROOT#", &name)
+}
+
+fn expression(op: &Operation, children: &[String], function: Option<&str>) -> String {
+    let child = |i: usize| children[i].as_str();
+    let call = |point: &str| format!("{}({}, t)", function.unwrap_or_default(), point);
+    match op {
+        Operation::Sum(..) => format!("({} + {})", child(0), child(1)),
+        Operation::Product(..) => format!("({} * {})", child(0), child(1)),
+        Operation::Mod(..) => format!("mod_fn({}, {})", child(0), child(1)),
+        Operation::Constant(value) => format!("vec3({})", float_literal(*value)),
+        Operation::VarX => "vec3(x)".to_string(),
+        Operation::VarY => "vec3(y)".to_string(),
+        Operation::VarT => "vec3(t)".to_string(),
+        Operation::Circle(cx, cy) => format!(
+            "vec3(distance(vec2(x, y), vec2({}, {})))",
+            float_literal(*cx),
+            float_literal(*cy)
+        ),
+        Operation::Inverse(..) => format!("(vec3(0.0) - {})", child(0)),
+        Operation::PerChannelMask(.., threshold) => {
+            let (m, a, b, threshold) = (child(0), child(1), child(2), float_literal(*threshold));
+            format!(
+                "vec3(({m}.x > {threshold} ? {a}.x : {b}.x), ({m}.y > {threshold} ? {a}.y : {b}.y), ({m}.z > {threshold} ? {a}.z : {b}.z))"
+            )
+        }
+        Operation::Sine(..) => format!("sin({})", child(0)),
+        Operation::BinaryMask(.., threshold) => format!(
+            "(length({}) > {} ? {} : {})",
+            child(0),
+            float_literal(*threshold),
+            child(1),
+            child(2)
+        ),
+        Operation::SmoothMix(..) => format!(
+            "(({} * {}) + ((1.0 - {}) * {}))",
+            child(0),
+            child(1),
+            child(0),
+            child(2)
+        ),
+        Operation::Well(..) => format!("well_fn({})", child(0)),
+        Operation::Tent(..) => format!("tent_fn({})", child(0)),
+        Operation::RGB(..) => format!("vec3({}.x, {}.y, {}.z)", child(0), child(1), child(2)),
+        Operation::Cos(..) => format!("cos({})", child(0)),
+        Operation::Tan(..) => format!("tan_fn({})", child(0)),
+        Operation::Atan2(..) => format!("atan2_fn({}, {})", child(0), child(1)),
+        Operation::Exp(..) => format!("exp_fn({})", child(0)),
+        Operation::Log(..) => format!("log_fn({})", child(0)),
+        Operation::Pow(..) => format!("pow_fn({}, {})", child(0), child(1)),
+        Operation::Abs(..) => format!("abs({})", child(0)),
+        Operation::Sqrt(..) => format!("sqrt(abs({}))", child(0)),
+        Operation::Min(..) => format!("min({}, {})", child(0), child(1)),
+        Operation::Max(..) => format!("max({}, {})", child(0), child(1)),
+        Operation::Clamp(_, low, high) => {
+            // min(max()) rather than clamp(), which is undefined for low > high
+            format!(
+                "min(max({}, {}), {})",
+                child(0),
+                float_literal(*low),
+                float_literal(*high)
+            )
+        }
+        Operation::Perlin(.., seed) => format!("perlin_fn({}, {}, {}u)", child(0), child(1), seed),
+        Operation::Simplex(.., seed) => {
+            format!("simplex_fn({}, {}, {}u)", child(0), child(1), seed)
+        }
+        Operation::ValueNoise(.., seed) => {
+            format!("value_noise_fn({}, {}, {}u)", child(0), child(1), seed)
+        }
+        Operation::Worley(.., seed) => format!("worley_fn({}, {}, {}u)", child(0), child(1), seed),
+        Operation::Fbm(_, _, seed, octaves) => format!(
+            "fbm_fn({}, {}, {}u, {}u)",
+            child(0),
+            child(1),
+            seed,
+            octaves
+        ),
+        Operation::Mandelbrot(..) => format!("mandelbrot_fn({}, {})", child(0), child(1)),
+        Operation::Julia(_, _, c_re, c_im) => format!(
+            "julia_fn({}, {}, {}, {})",
+            child(0),
+            child(1),
+            float_literal(*c_re),
+            float_literal(*c_im)
+        ),
+        Operation::Rotate(angle, _) => {
+            let (sin, cos) = angle.sin_cos();
+            let (sin, cos) = (float_literal(sin), float_literal(cos));
+            call(&format!(
+                "vec2(x * {cos} + y * {sin}, y * {cos} - x * {sin})"
+            ))
+        }
+        Operation::Scale(sx, sy, _) => call(&format!(
+            "vec2(x * {}, y * {})",
+            float_literal(*sx),
+            float_literal(*sy)
+        )),
+        Operation::Translate(dx, dy, _) => call(&format!(
+            "vec2(x - {}, y - {})",
+            float_literal(*dx),
+            float_literal(*dy)
+        )),
+        Operation::Polar(_) => call("vec2(length(vec2(x, y)), atan2_fn(y, x))"),
+        Operation::Warp(..) => call(&format!(
+            "vec2(x + mean_fn({}), y + mean_fn({}))",
+            child(0),
+            child(1)
+        )),
+        Operation::Mirror(_) => call("vec2(abs(x), y)"),
+        Operation::Kaleidoscope(n, _) => call(&format!("kaleidoscope_fn(x, y, {}.0)", n)),
+        Operation::Image(id) => format!("sample_image_{}(x, y)", id),
+    }
+}
+
+fn variable(name: &str, expression: &str) -> String {
+    format!("    vec3 {} = {};\n", name, expression)
+}
+
+fn function(name: &str, statements: &str, result: &str) -> String {
+    format!(
+        "vec3 {}(vec2 xy, float t) {{\n    float x = xy.x;\n    float y = xy.y;\n{}    return {};\n}}\n\n",
+        name, statements, result
+    )
+}
+
+const FRAGMENT_SHADER: &str = r#"#HEADER#
+out vec4 finalColor;
+uniform float time;
+uniform vec2 resolution;
+// center x, center y, half extent of the shorter side
+uniform vec3 domain;
+
+vec4 map_color(vec3 rgb) {
+    #MAP_COLOR#
+}
+#HELPERS#
+#NOISE_FUNCTIONS#
+#IMAGE_FUNCTIONS#
+#FUNCTIONS#
+void main() {
+    // Same mapping as RenderSpec::pixel_to_point
+    vec2 pixel = #PIXEL#;
+    float scale = 2.0 * domain.z / min(resolution.x, resolution.y);
+    float x = domain.x + (pixel.x - resolution.x / 2.0) * scale;
+    float y = domain.y + (pixel.y - resolution.y / 2.0) * scale;
+    float t = #TIME#;
+    finalColor = map_color(#ROOT#(vec2(x, y), t));
+}
+"#;
+
+const SHADERTOY_SHADER: &str = r#"// Bind the images sampled by image(<id>) leaves to iChannel<id>
+
+// center x, center y, half extent of the shorter side
+const vec3 domain = #DOMAIN#;
+
+vec4 map_color(vec3 rgb) {
+    #MAP_COLOR#
+}
+#HELPERS#
+#NOISE_FUNCTIONS#
+#IMAGE_FUNCTIONS#
+#FUNCTIONS#
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+    // Same mapping as RenderSpec::pixel_to_point; fragment coordinates start
+    // at the bottom, while image rows start at the top
+    vec2 resolution = iResolution.xy;
+    float time = iTime;
+    vec2 pixel = vec2(fragCoord.x, resolution.y - fragCoord.y);
+    float scale = 2.0 * domain.z / min(resolution.x, resolution.y);
+    float x = domain.x + (pixel.x - resolution.x / 2.0) * scale;
+    float y = domain.y + (pixel.y - resolution.y / 2.0) * scale;
+    float t = #TIME#;
+    fragColor = map_color(#ROOT#(vec2(x, y), t));
+}
+"#;
+
+const HELPERS: &str = r#"
+vec3 mod_fn(vec3 x, vec3 y) {
+    // Like f64::rem_euclid, never negative; mod() follows the sign of y
+    return x - abs(y) * floor(x / abs(y));
+}
+
+vec3 well_fn(vec3 x) {
+    // pow() is undefined for negative bases
+    vec3 w = 1.0 - 2.0 / (1.0 + x * x);
+    w *= w;
+    w *= w;
+    return w * w;
+}
+
+vec3 tent_fn(vec3 x) {
+    return 1.0 - 2.0 * abs(x);
+}
+
+// Keep in sync with Operation::escape_time_fn, FRACTAL_ITERATIONS and ESCAPE_RADIUS
+float escape_time_fn(float z_re, float z_im, float c_re, float c_im) {
+    for (int n = 0; n < 64; n++) {
+        float re2 = z_re * z_re;
+        float im2 = z_im * z_im;
+        if (re2 + im2 > 256.0 * 256.0) {
+            float smooth_n = float(n) + 1.0 - log2(log(re2 + im2) / 2.0);
+            return 2.0 * clamp(smooth_n, 0.0, 64.0) / 64.0 - 1.0;
+        }
+        z_im = 2.0 * z_re * z_im + c_im;
+        z_re = re2 - im2 + c_re;
+    }
+    return 1.0;
+}
+
+vec3 mandelbrot_fn(vec3 re, vec3 im) {
+    return vec3(escape_time_fn(0.0, 0.0, re.x, im.x), escape_time_fn(0.0, 0.0, re.y, im.y),
+                escape_time_fn(0.0, 0.0, re.z, im.z));
+}
+
+vec3 julia_fn(vec3 re, vec3 im, float c_re, float c_im) {
+    return vec3(escape_time_fn(re.x, im.x, c_re, c_im), escape_time_fn(re.y, im.y, c_re, c_im),
+                escape_time_fn(re.z, im.z, c_re, c_im));
+}
+
+// Keep in sync with TAN_LIMIT, EXP_LIMIT and EPSILON in operations.rs
+vec3 tan_fn(vec3 x) {
+    return clamp(tan(x), -10.0, 10.0);
+}
+
+float atan2_fn(float y, float x) {
+    // atan(0, 0) is undefined in GLSL, while Rust returns 0
+    return (x == 0.0 && y == 0.0) ? 0.0 : atan(y, x) / 3.14159265358979;
+}
+
+vec3 atan2_fn(vec3 y, vec3 x) {
+    return vec3(atan2_fn(y.x, x.x), atan2_fn(y.y, x.y), atan2_fn(y.z, x.z));
+}
+
+vec3 exp_fn(vec3 x) {
+    return exp(min(x, 10.0));
+}
+
+vec3 log_fn(vec3 x) {
+    return log(max(abs(x), 1e-6));
+}
+
+vec3 pow_fn(vec3 x, vec3 y) {
+    return pow(max(abs(x), 1e-6), y);
+}
+
+float mean_fn(vec3 v) {
+    return (v.x + v.y + v.z) / 3.0;
+}
+
+vec2 kaleidoscope_fn(float x, float y, float n) {
+    float wedge = 6.28318530717959 / max(n, 1.0);
+    float angle = abs(mod(atan2_fn(y, x) * 3.14159265358979, wedge) - wedge / 2.0);
+    float r = length(vec2(x, y));
+    return vec2(r * cos(angle), r * sin(angle));
+}
+"#;
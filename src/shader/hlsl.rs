@@ -0,0 +1,459 @@
+use crate::images::{ImageSet, WrapMode};
+use crate::operations::Operation;
+use crate::renderer::{ColorMapping, RenderSpec, TimeMapping};
+use crate::shader::{float_literal, tree_functions, ShaderBackend};
+
+/// HLSL (shader model 4 and later), with entry point `main`. The uniforms are
+/// a constant buffer at register `b0`, and image `id` is a `Texture2D` at
+/// register `t<id>`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hlsl;
+
+impl ShaderBackend for Hlsl {
+    fn fragment_shader(&self, root: &Operation, spec: &RenderSpec, images: &ImageSet) -> String {
+        let (name, functions) = tree_functions(self, root);
+        let map_color = match spec.color {
+            ColorMapping::Signed => "return float4(saturate((rgb + 1.0) / 2.0), 1.0);",
+            ColorMapping::Clamp => "return float4(saturate(rgb), 1.0);",
+        };
+        let time = match spec.time {
+            TimeMapping::Fixed(t) => float_literal(t),
+            TimeMapping::Sine => "sin(time)".to_string(),
+        };
+        FRAGMENT_SHADER
+            .replace("#MAP_COLOR#", map_color)
+            .replace("#HELPERS#", HELPERS)
+            .replace("#NOISE_FUNCTIONS#", NOISE_FUNCTIONS)
+            .replace("#IMAGE_FUNCTIONS#", &image_functions(images, root))
+            .replace("#FUNCTIONS#", &functions)
+            .replace("#TIME#", &time)
+            .replace("#ROOT#", &name)
+    }
+
+    fn expression(&self, op: &Operation, children: &[String], function: Option<&str>) -> String {
+        let child = |i: usize| children[i].as_str();
+        let call = |point: &str| format!("{}({}, t)", function.unwrap_or_default(), point);
+        let splat = |value: &str| format!("float3({}, {}, {})", value, value, value);
+        match op {
+            Operation::Sum(..) => format!("({} + {})", child(0), child(1)),
+            Operation::Product(..) => format!("({} * {})", child(0), child(1)),
+            Operation::Mod(..) => format!("mod_fn({}, {})", child(0), child(1)),
+            Operation::Constant(value) => splat(&float_literal(*value)),
+            Operation::VarX => splat("x"),
+            Operation::VarY => splat("y"),
+            Operation::VarT => splat("t"),
+            Operation::Circle(cx, cy) => splat(&format!(
+                "distance(float2(x, y), float2({}, {}))",
+                float_literal(*cx),
+                float_literal(*cy)
+            )),
+            Operation::Inverse(..) => format!("(float3(0.0, 0.0, 0.0) - {})", child(0)),
+            Operation::PerChannelMask(.., threshold) => {
+                let (m, a, b, threshold) =
+                    (child(0), child(1), child(2), float_literal(*threshold));
+                format!(
+                    "float3(({m}.x > {threshold} ? {a}.x : {b}.x), ({m}.y > {threshold} ? {a}.y : {b}.y), ({m}.z > {threshold} ? {a}.z : {b}.z))"
+                )
+            }
+            Operation::Sine(..) => format!("sin({})", child(0)),
+            Operation::BinaryMask(.., threshold) => format!(
+                "(length({}) > {} ? {} : {})",
+                child(0),
+                float_literal(*threshold),
+                child(1),
+                child(2)
+            ),
+            Operation::SmoothMix(..) => format!(
+                "(({} * {}) + ((1.0 - {}) * {}))",
+                child(0),
+                child(1),
+                child(0),
+                child(2)
+            ),
+            Operation::Well(..) => format!("well_fn({})", child(0)),
+            Operation::Tent(..) => format!("tent_fn({})", child(0)),
+            Operation::RGB(..) => {
+                format!("float3({}.x, {}.y, {}.z)", child(0), child(1), child(2))
+            }
+            Operation::Cos(..) => format!("cos({})", child(0)),
+            Operation::Tan(..) => format!("tan_fn({})", child(0)),
+            Operation::Atan2(..) => format!("atan2_fn({}, {})", child(0), child(1)),
+            Operation::Exp(..) => format!("exp_fn({})", child(0)),
+            Operation::Log(..) => format!("log_fn({})", child(0)),
+            Operation::Pow(..) => format!("pow_fn({}, {})", child(0), child(1)),
+            Operation::Abs(..) => format!("abs({})", child(0)),
+            Operation::Sqrt(..) => format!("sqrt(abs({}))", child(0)),
+            Operation::Min(..) => format!("min({}, {})", child(0), child(1)),
+            Operation::Max(..) => format!("max({}, {})", child(0), child(1)),
+            Operation::Clamp(_, low, high) => {
+                // min(max()) rather than clamp(), which is undefined for low > high
+                format!(
+                    "min(max({}, {}), {})",
+                    child(0),
+                    splat(&float_literal(*low)),
+                    splat(&float_literal(*high))
+                )
+            }
+            Operation::Perlin(.., seed) => {
+                format!("perlin_fn({}, {}, {}u)", child(0), child(1), seed)
+            }
+            Operation::Simplex(.., seed) => {
+                format!("simplex_fn({}, {}, {}u)", child(0), child(1), seed)
+            }
+            Operation::ValueNoise(.., seed) => {
+                format!("value_noise_fn({}, {}, {}u)", child(0), child(1), seed)
+            }
+            Operation::Worley(.., seed) => {
+                format!("worley_fn({}, {}, {}u)", child(0), child(1), seed)
+            }
+            Operation::Fbm(_, _, seed, octaves) => format!(
+                "fbm_fn({}, {}, {}u, {}u)",
+                child(0),
+                child(1),
+                seed,
+                octaves
+            ),
+            Operation::Mandelbrot(..) => format!("mandelbrot_fn({}, {})", child(0), child(1)),
+            Operation::Julia(_, _, c_re, c_im) => format!(
+                "julia_fn({}, {}, {}, {})",
+                child(0),
+                child(1),
+                float_literal(*c_re),
+                float_literal(*c_im)
+            ),
+            Operation::Rotate(angle, _) => {
+                let (sin, cos) = angle.sin_cos();
+                let (sin, cos) = (float_literal(sin), float_literal(cos));
+                call(&format!(
+                    "float2(x * {cos} + y * {sin}, y * {cos} - x * {sin})"
+                ))
+            }
+            Operation::Scale(sx, sy, _) => call(&format!(
+                "float2(x * {}, y * {})",
+                float_literal(*sx),
+                float_literal(*sy)
+            )),
+            Operation::Translate(dx, dy, _) => call(&format!(
+                "float2(x - {}, y - {})",
+                float_literal(*dx),
+                float_literal(*dy)
+            )),
+            Operation::Polar(_) => call("float2(length(float2(x, y)), atan2_fn(y, x))"),
+            Operation::Warp(..) => call(&format!(
+                "float2(x + mean_fn({}), y + mean_fn({}))",
+                child(0),
+                child(1)
+            )),
+            Operation::Mirror(_) => call("float2(abs(x), y)"),
+            Operation::Kaleidoscope(n, _) => call(&format!("kaleidoscope_fn(x, y, {}.0)", n)),
+            Operation::Image(id) => format!("sample_image_{}(x, y)", id),
+        }
+    }
+
+    fn variable(&self, name: &str, expression: &str) -> String {
+        format!("    float3 {} = {};\n", name, expression)
+    }
+
+    fn function(&self, name: &str, statements: &str, result: &str) -> String {
+        format!(
+            "float3 {}(float2 xy, float t) {{\n    float x = xy.x;\n    float y = xy.y;\n{}    return {};\n}}\n\n",
+            name, statements, result
+        )
+    }
+}
+
+fn image_functions(images: &ImageSet, root: &Operation) -> String {
+    let mut hlsl = String::new();
+    for id in 0..images.sampled_count(root) {
+        match images.images().get(id) {
+            Some(image) => {
+                let size = format!(
+                    "float2({}.0, {}.0)",
+                    image.image().width(),
+                    image.image().height()
+                );
+                let wrap = match image.wrap() {
+                    WrapMode::Repeat => "cell - size * floor(cell / size)",
+                    WrapMode::Clamp => "clamp(cell, float2(0.0, 0.0), size - 1.0)",
+                };
+                hlsl.push_str(
+                    &SAMPLE_IMAGE
+                        .replace("#ID#", &id.to_string())
+                        .replace("#SIZE#", &size)
+                        .replace("#WRAP#", wrap),
+                );
+            }
+            None => hlsl.push_str(&format!(
+                "float3 sample_image_{id}(float x, float y) {{\n    return float3(0.0, 0.0, 0.0);\n}}\n"
+            )),
+        }
+    }
+    hlsl
+}
+
+const FRAGMENT_SHADER: &str = r#"cbuffer Uniforms : register(b0) {
+    float time;
+    float2 resolution;
+    // center x, center y, half extent of the shorter side
+    float3 domain;
+};
+
+float4 map_color(float3 rgb) {
+    #MAP_COLOR#
+}
+#HELPERS#
+#NOISE_FUNCTIONS#
+#IMAGE_FUNCTIONS#
+#FUNCTIONS#
+float4 main(float4 position : SV_Position) : SV_Target {
+    // Same mapping as RenderSpec::pixel_to_point; pixel positions start at
+    // the top, like image rows
+    float scale = 2.0 * domain.z / min(resolution.x, resolution.y);
+    float x = domain.x + (position.x - resolution.x / 2.0) * scale;
+    float y = domain.y + (position.y - resolution.y / 2.0) * scale;
+    float t = #TIME#;
+    return map_color(#ROOT#(float2(x, y), t));
+}
+"#;
+
+const HELPERS: &str = r#"
+float3 mod_fn(float3 x, float3 y) {
+    // Like f64::rem_euclid, never negative; fmod follows the sign of x
+    return x - abs(y) * floor(x / abs(y));
+}
+
+float3 well_fn(float3 x) {
+    // pow() is undefined for negative bases
+    float3 w = 1.0 - 2.0 / (1.0 + x * x);
+    w *= w;
+    w *= w;
+    return w * w;
+}
+
+float3 tent_fn(float3 x) {
+    return 1.0 - 2.0 * abs(x);
+}
+
+// Keep in sync with Operation::escape_time_fn, FRACTAL_ITERATIONS and ESCAPE_RADIUS
+float escape_time_fn(float z_re, float z_im, float c_re, float c_im) {
+    for (int n = 0; n < 64; n++) {
+        float re2 = z_re * z_re;
+        float im2 = z_im * z_im;
+        if (re2 + im2 > 256.0 * 256.0) {
+            float smooth_n = float(n) + 1.0 - log2(log(re2 + im2) / 2.0);
+            return 2.0 * clamp(smooth_n, 0.0, 64.0) / 64.0 - 1.0;
+        }
+        z_im = 2.0 * z_re * z_im + c_im;
+        z_re = re2 - im2 + c_re;
+    }
+    return 1.0;
+}
+
+float3 mandelbrot_fn(float3 re, float3 im) {
+    return float3(escape_time_fn(0.0, 0.0, re.x, im.x), escape_time_fn(0.0, 0.0, re.y, im.y),
+                  escape_time_fn(0.0, 0.0, re.z, im.z));
+}
+
+float3 julia_fn(float3 re, float3 im, float c_re, float c_im) {
+    return float3(escape_time_fn(re.x, im.x, c_re, c_im), escape_time_fn(re.y, im.y, c_re, c_im),
+                  escape_time_fn(re.z, im.z, c_re, c_im));
+}
+
+// Keep in sync with TAN_LIMIT, EXP_LIMIT and EPSILON in operations.rs
+float3 tan_fn(float3 x) {
+    return clamp(tan(x), -10.0, 10.0);
+}
+
+float atan2_fn(float y, float x) {
+    // Return 0 for atan2(0, 0) like Rust
+    return (x == 0.0 && y == 0.0) ? 0.0 : atan2(y, x) / 3.14159265358979;
+}
+
+float3 atan2_fn(float3 y, float3 x) {
+    return float3(atan2_fn(y.x, x.x), atan2_fn(y.y, x.y), atan2_fn(y.z, x.z));
+}
+
+float3 exp_fn(float3 x) {
+    return exp(min(x, 10.0));
+}
+
+float3 log_fn(float3 x) {
+    return log(max(abs(x), 1e-6));
+}
+
+float3 pow_fn(float3 x, float3 y) {
+    return pow(max(abs(x), 1e-6), y);
+}
+
+float mean_fn(float3 v) {
+    return (v.x + v.y + v.z) / 3.0;
+}
+
+float2 kaleidoscope_fn(float x, float y, float n) {
+    float wedge = 6.28318530717959 / max(n, 1.0);
+    float a = atan2_fn(y, x) * 3.14159265358979;
+    float angle = abs(a - wedge * floor(a / wedge) - wedge / 2.0);
+    float r = length(float2(x, y));
+    return float2(r * cos(angle), r * sin(angle));
+}
+"#;
+
+const NOISE_FUNCTIONS: &str = r#"
+// Keep in sync with noise.rs
+static const float NOISE_SCALE = 4.0;
+static const float NOISE_LIMIT = 4096.0;
+static const uint MAX_OCTAVES = 8u;
+
+static const float2 GRADIENTS[8] = {
+    float2(1.0, 1.0), float2(-1.0, 1.0), float2(1.0, -1.0), float2(-1.0, -1.0),
+    float2(1.0, 0.0), float2(-1.0, 0.0), float2(0.0, 1.0), float2(0.0, -1.0)
+};
+
+uint noise_hash(uint h) {
+    h ^= h >> 16;
+    h *= 0x7feb352du;
+    h ^= h >> 15;
+    h *= 0x846ca68bu;
+    h ^= h >> 16;
+    return h;
+}
+
+uint hash_cell(int2 cell, uint seed) {
+    return noise_hash(asuint(cell.x) ^ noise_hash(asuint(cell.y) ^ noise_hash(seed)));
+}
+
+float noise_unit(uint h) {
+    return float(h) / 4294967296.0;
+}
+
+float2 noise_prepare(float x, float y) {
+    return clamp(float2(x, y) * NOISE_SCALE, -NOISE_LIMIT, NOISE_LIMIT);
+}
+
+float2 noise_fade(float2 t) {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+float noise_gradient(uint h, float2 d) {
+    return dot(GRADIENTS[h & 7u], d);
+}
+
+float perlin_fn(float x, float y, uint seed) {
+    float2 p = noise_prepare(x, y);
+    float2 p0 = floor(p);
+    float2 f = p - p0;
+    int2 i = int2(p0);
+    float n00 = noise_gradient(hash_cell(i, seed), f);
+    float n10 = noise_gradient(hash_cell(i + int2(1, 0), seed), f - float2(1.0, 0.0));
+    float n01 = noise_gradient(hash_cell(i + int2(0, 1), seed), f - float2(0.0, 1.0));
+    float n11 = noise_gradient(hash_cell(i + int2(1, 1), seed), f - float2(1.0, 1.0));
+    float2 u = noise_fade(f);
+    return lerp(lerp(n00, n10, u.x), lerp(n01, n11, u.x), u.y);
+}
+
+float value_noise_fn(float x, float y, uint seed) {
+    float2 p = noise_prepare(x, y);
+    float2 p0 = floor(p);
+    float2 f = p - p0;
+    int2 i = int2(p0);
+    float n00 = 2.0 * noise_unit(hash_cell(i, seed)) - 1.0;
+    float n10 = 2.0 * noise_unit(hash_cell(i + int2(1, 0), seed)) - 1.0;
+    float n01 = 2.0 * noise_unit(hash_cell(i + int2(0, 1), seed)) - 1.0;
+    float n11 = 2.0 * noise_unit(hash_cell(i + int2(1, 1), seed)) - 1.0;
+    float2 u = noise_fade(f);
+    return lerp(lerp(n00, n10, u.x), lerp(n01, n11, u.x), u.y);
+}
+
+float simplex_corner(int2 cell, uint seed, float2 d) {
+    float falloff = 0.5 - dot(d, d);
+    if (falloff < 0.0) {
+        return 0.0;
+    }
+    falloff *= falloff;
+    return falloff * falloff * noise_gradient(hash_cell(cell, seed), d);
+}
+
+float simplex_fn(float x, float y, uint seed) {
+    const float F2 = 0.3660254037844386;
+    const float G2 = 0.2113248654051871;
+    float2 p = noise_prepare(x, y);
+    float2 ij = floor(p + (p.x + p.y) * F2);
+    float2 d0 = p - (ij - (ij.x + ij.y) * G2);
+    int2 o = d0.x > d0.y ? int2(1, 0) : int2(0, 1);
+    int2 i = int2(ij);
+    float n0 = simplex_corner(i, seed, d0);
+    float n1 = simplex_corner(i + o, seed, d0 - float2(o) + G2);
+    float n2 = simplex_corner(i + int2(1, 1), seed, d0 - 1.0 + 2.0 * G2);
+    return 70.0 * (n0 + n1 + n2);
+}
+
+float worley_fn(float x, float y, uint seed) {
+    float2 p = noise_prepare(x, y);
+    float2 p0 = floor(p);
+    float2 f = p - p0;
+    int2 i = int2(p0);
+    float closest = 1.0;
+    for (int j = -1; j <= 1; j++) {
+        for (int k = -1; k <= 1; k++) {
+            uint h = hash_cell(i + int2(k, j), seed);
+            float2 d = float2(k, j) + float2(noise_unit(h), noise_unit(noise_hash(h))) - f;
+            closest = min(closest, sqrt(dot(d, d)));
+        }
+    }
+    return 2.0 * closest - 1.0;
+}
+
+float fbm_fn(float x, float y, uint seed, uint octaves) {
+    float sum = 0.0;
+    float total = 0.0;
+    float amplitude = 1.0;
+    float frequency = 1.0;
+    for (uint octave = 0u; octave < clamp(octaves, 1u, MAX_OCTAVES); octave++) {
+        sum += amplitude * perlin_fn(x * frequency, y * frequency, seed + octave);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return sum / total;
+}
+
+float3 perlin_fn(float3 x, float3 y, uint seed) {
+    return float3(perlin_fn(x.x, y.x, seed), perlin_fn(x.y, y.y, seed), perlin_fn(x.z, y.z, seed));
+}
+
+float3 value_noise_fn(float3 x, float3 y, uint seed) {
+    return float3(value_noise_fn(x.x, y.x, seed), value_noise_fn(x.y, y.y, seed), value_noise_fn(x.z, y.z, seed));
+}
+
+float3 simplex_fn(float3 x, float3 y, uint seed) {
+    return float3(simplex_fn(x.x, y.x, seed), simplex_fn(x.y, y.y, seed), simplex_fn(x.z, y.z, seed));
+}
+
+float3 worley_fn(float3 x, float3 y, uint seed) {
+    return float3(worley_fn(x.x, y.x, seed), worley_fn(x.y, y.y, seed), worley_fn(x.z, y.z, seed));
+}
+
+float3 fbm_fn(float3 x, float3 y, uint seed, uint octaves) {
+    return float3(fbm_fn(x.x, y.x, seed, octaves), fbm_fn(x.y, y.y, seed, octaves), fbm_fn(x.z, y.z, seed, octaves));
+}
+"#;
+
+// Keep in sync with InputImage::sample
+const SAMPLE_IMAGE: &str = r#"
+Texture2D<float4> image_#ID# : register(t#ID#);
+
+float3 image_texel_#ID#(float2 cell) {
+    float2 size = #SIZE#;
+    cell = #WRAP#;
+    return image_#ID#.Load(int3(int2(cell), 0)).rgb * 2.0 - 1.0;
+}
+
+float3 sample_image_#ID#(float x, float y) {
+    float2 p = clamp((float2(x, y) + 1.0) / 2.0 * #SIZE# - 0.5, -16777216.0, 16777216.0);
+    float2 p0 = floor(p);
+    float2 f = p - p0;
+    float3 top = lerp(image_texel_#ID#(p0), image_texel_#ID#(p0 + float2(1.0, 0.0)), f.x);
+    float3 bottom = lerp(image_texel_#ID#(p0 + float2(0.0, 1.0)), image_texel_#ID#(p0 + float2(1.0, 1.0)), f.x);
+    return lerp(top, bottom, f.y);
+}
+"#;
@@ -0,0 +1,477 @@
+//! Fragment shader generation for expression trees.
+//!
+//! Every backend produces a complete fragment shader with the same inputs: a
+//! `time` uniform in seconds, the `resolution` in pixels, the `domain`
+//! (center x, center y and half extent of the shorter side, see
+//! [`Domain`](crate::renderer::Domain)) and one texture `image_<id>` per
+//! input image. Pixels map to points and colors exactly like in the
+//! [`RenderSpec`] passed to the backend.
+
+mod glsl;
+mod hlsl;
+mod wgsl;
+
+pub use glsl::{Glsl330, GlslEs300, Shadertoy};
+pub use hlsl::Hlsl;
+pub use wgsl::Wgsl;
+
+use crate::images::ImageSet;
+use crate::operations::Operation;
+use crate::renderer::RenderSpec;
+use std::collections::HashMap;
+
+/// A shading language the expression trees can be compiled to.
+pub trait ShaderBackend {
+    /// A complete, self-contained fragment shader rendering `root` as
+    /// described by `spec`, sampling image leaves from textures of `images`.
+    fn fragment_shader(&self, root: &Operation, spec: &RenderSpec, images: &ImageSet) -> String;
+
+    /// Expression computing `op` as a three-component vector, given the
+    /// variables holding its children. Coordinate transforms get the
+    /// variables of all but their last child, and the name of the function
+    /// evaluating the last child at a point, as written by
+    /// [`ShaderBackend::function`].
+    fn expression(&self, op: &Operation, children: &[String], function: Option<&str>) -> String;
+
+    /// Statement declaring a local variable holding `expression`.
+    fn variable(&self, name: &str, expression: &str) -> String;
+
+    /// Definition of a function `name` taking a two-component point `xy` and
+    /// the time `t`, which declares `x` and `y`, runs `statements` and
+    /// returns `result`.
+    fn function(&self, name: &str, statements: &str, result: &str) -> String;
+}
+
+/// Formats `value` as a float literal for every backend. Debug keeps a
+/// decimal point or an exponent, as GLSL ES and WGSL do not convert integers
+/// implicitly. Shaders compute in `f32` and have no literals for infinities
+/// or NaN, so values are clamped to the range of `f32` and NaN becomes zero.
+pub(crate) fn float_literal(value: f64) -> String {
+    if value.is_nan() {
+        return "0.0".to_string();
+    }
+    format!("{:?}", value.clamp(f32::MIN as f64, f32::MAX as f64))
+}
+
+/// Writes the functions evaluating `root` with `backend`, returning the
+/// name of the function evaluating the whole tree and the definitions of
+/// all functions, callees first.
+///
+/// Every node is stored in a local variable and structurally identical
+/// nodes, and identical functions, are only written once, so the source
+/// grows linearly with the size of the tree.
+pub fn tree_functions(backend: &dyn ShaderBackend, root: &Operation) -> (String, String) {
+    let mut writer = ShaderWriter {
+        backend,
+        functions: Vec::new(),
+        names: HashMap::new(),
+    };
+    let name = writer.function(root);
+    (name, writer.functions.concat())
+}
+
+struct ShaderWriter<'a> {
+    backend: &'a dyn ShaderBackend,
+    functions: Vec<String>,
+    names: HashMap<String, String>,
+}
+
+/// The statements of a function and its local variables, keyed by the
+/// expression they hold.
+#[derive(Default)]
+struct Scope {
+    statements: String,
+    variables: HashMap<String, String>,
+}
+
+impl ShaderWriter<'_> {
+    /// Returns the name of a function evaluating `op` at a point.
+    fn function(&mut self, op: &Operation) -> String {
+        let mut scope = Scope::default();
+        let result = self.variable(op, &mut scope);
+        let key = format!("{}{}", scope.statements, result);
+        if let Some(name) = self.names.get(&key) {
+            return name.clone();
+        }
+        let name = format!("node_{}", self.functions.len());
+        self.functions
+            .push(self.backend.function(&name, &scope.statements, &result));
+        self.names.insert(key, name.clone());
+        name
+    }
+
+    /// Returns the variable of `scope` holding `op`, declaring it and the
+    /// variables of its children unless the same expression already has one.
+    fn variable(&mut self, op: &Operation, scope: &mut Scope) -> String {
+        let mut children = op.children();
+        let function = if op.is_transform() {
+            // Evaluated at other coordinates, in a function of its own
+            children.pop().map(|child| self.function(child))
+        } else {
+            None
+        };
+        let children: Vec<String> = children
+            .into_iter()
+            .map(|child| self.variable(child, scope))
+            .collect();
+        let expression = self.backend.expression(op, &children, function.as_deref());
+        if let Some(variable) = scope.variables.get(&expression) {
+            return variable.clone();
+        }
+        let variable = format!("v{}", scope.variables.len());
+        scope
+            .statements
+            .push_str(&self.backend.variable(&variable, &expression));
+        scope.variables.insert(expression, variable.clone());
+        variable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::{
+        ArtGrammar, OperationKind, OperationWeights, PerrigSongGrammar, RandomArtGrammar,
+    };
+    use crate::images::{InputImage, WrapMode};
+    use crate::seed::Seed;
+    use image::RgbImage;
+    use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+    fn test_images() -> ImageSet {
+        ImageSet::new(vec![
+            InputImage::new(RgbImage::new(4, 3), WrapMode::Repeat),
+            InputImage::new(RgbImage::new(2, 5), WrapMode::Clamp),
+        ])
+    }
+
+    /// Trees from both grammars with every operation, together covering
+    /// every [`OperationKind`]. Image leaves use ids 0 to 2, so one has no image.
+    fn test_trees() -> Vec<Operation> {
+        let weights = OperationWeights::uniform().with_image_count(3);
+        let mut trees = Vec::new();
+        for i in 0..40 {
+            let seed = Seed::from_string(&format!("shader {}", i));
+            let depth = 2 + i % 5;
+            trees.push(
+                RandomArtGrammar::from_seed(&seed)
+                    .with_weights(weights.clone())
+                    .generate_tree(depth),
+            );
+            trees.push(
+                PerrigSongGrammar::from_seed(&seed)
+                    .with_weights(weights.clone())
+                    .generate_tree(depth),
+            );
+        }
+        trees.push(RandomArtGrammar::new(1).generate_tree(6));
+        trees.push(PerrigSongGrammar::new(1).generate_tree(6));
+        // Values without a literal in the shading languages
+        trees.push(Operation::RGB(
+            Operation::Sum(
+                Operation::Constant(f64::INFINITY).into(),
+                Operation::Constant(f64::NEG_INFINITY).into(),
+            )
+            .into(),
+            Operation::Product(
+                Operation::Constant(f64::NAN).into(),
+                Operation::Circle(1e50, -1e-50).into(),
+            )
+            .into(),
+            Operation::Rotate(
+                f64::INFINITY,
+                Operation::Scale(1e50, f64::NAN, Operation::VarX.into()).into(),
+            )
+            .into(),
+        ));
+        trees
+    }
+
+    fn kinds(op: &Operation, found: &mut Vec<OperationKind>) {
+        found.push(match op {
+            Operation::Constant(..) => OperationKind::Constant,
+            Operation::VarX => OperationKind::VarX,
+            Operation::VarY => OperationKind::VarY,
+            Operation::VarT => OperationKind::VarT,
+            Operation::Circle(..) => OperationKind::Circle,
+            Operation::Sum(..) => OperationKind::Sum,
+            Operation::Product(..) => OperationKind::Product,
+            Operation::Mod(..) => OperationKind::Mod,
+            Operation::Sine(..) => OperationKind::Sine,
+            Operation::Inverse(..) => OperationKind::Inverse,
+            Operation::PerChannelMask(..) => OperationKind::PerChannelMask,
+            Operation::BinaryMask(..) => OperationKind::BinaryMask,
+            Operation::SmoothMix(..) => OperationKind::SmoothMix,
+            Operation::Well(..) => OperationKind::Well,
+            Operation::Tent(..) => OperationKind::Tent,
+            Operation::RGB(..) => OperationKind::RGB,
+            Operation::Cos(..) => OperationKind::Cos,
+            Operation::Tan(..) => OperationKind::Tan,
+            Operation::Atan2(..) => OperationKind::Atan2,
+            Operation::Exp(..) => OperationKind::Exp,
+            Operation::Log(..) => OperationKind::Log,
+            Operation::Pow(..) => OperationKind::Pow,
+            Operation::Abs(..) => OperationKind::Abs,
+            Operation::Sqrt(..) => OperationKind::Sqrt,
+            Operation::Min(..) => OperationKind::Min,
+            Operation::Max(..) => OperationKind::Max,
+            Operation::Clamp(..) => OperationKind::Clamp,
+            Operation::Perlin(..) => OperationKind::Perlin,
+            Operation::Simplex(..) => OperationKind::Simplex,
+            Operation::ValueNoise(..) => OperationKind::ValueNoise,
+            Operation::Worley(..) => OperationKind::Worley,
+            Operation::Fbm(..) => OperationKind::Fbm,
+            Operation::Mandelbrot(..) => OperationKind::Mandelbrot,
+            Operation::Julia(..) => OperationKind::Julia,
+            Operation::Rotate(..) => OperationKind::Rotate,
+            Operation::Scale(..) => OperationKind::Scale,
+            Operation::Translate(..) => OperationKind::Translate,
+            Operation::Polar(..) => OperationKind::Polar,
+            Operation::Warp(..) => OperationKind::Warp,
+            Operation::Mirror(..) => OperationKind::Mirror,
+            Operation::Kaleidoscope(..) => OperationKind::Kaleidoscope,
+            Operation::Image(..) => OperationKind::Image,
+        });
+        for child in op.children() {
+            kinds(child, found);
+        }
+    }
+
+    #[test]
+    fn test_trees_cover_every_operation() {
+        let mut found = Vec::new();
+        for tree in test_trees() {
+            kinds(&tree, &mut found);
+        }
+        for kind in OperationKind::ALL {
+            assert!(found.contains(&kind), "no `{}` node", kind.name());
+        }
+    }
+
+    #[test]
+    fn float_literals_stay_in_f32_range() {
+        assert_eq!(float_literal(0.5), "0.5");
+        assert_eq!(float_literal(-2.0), "-2.0");
+        assert_eq!(float_literal(1e50), format!("{:?}", f32::MAX as f64));
+        assert_eq!(float_literal(f64::INFINITY), float_literal(1e50));
+        assert_eq!(
+            float_literal(f64::NEG_INFINITY),
+            format!("{:?}", f32::MIN as f64)
+        );
+        assert_eq!(float_literal(f64::NAN), "0.0");
+    }
+
+    fn validate(module: naga::Module, source: &str) {
+        if let Err(err) =
+            Validator::new(ValidationFlags::all(), Capabilities::all()).validate(&module)
+        {
+            panic!("{}\n{}", err.emit_to_string(source), source);
+        }
+    }
+
+    fn validate_wgsl(source: &str) {
+        match naga::front::wgsl::parse_str(source) {
+            Ok(module) => validate(module, source),
+            Err(err) => panic!("{}\n{}", err.emit_to_string(source), source),
+        }
+    }
+
+    /// naga only reads Vulkan GLSL, so the shader is rewritten to `#version
+    /// 450` with explicit locations, a uniform block and separate samplers.
+    fn validate_glsl(source: &str) {
+        let mut lines = source.lines();
+        let mut glsl = String::from("#version 450\n");
+        lines.next();
+        for line in lines {
+            let line = match line {
+                "precision highp float;" | "precision highp int;" => continue,
+                "uniform float time;" | "uniform vec2 resolution;" => continue,
+                "uniform vec3 domain;" => {
+                    "layout(set = 0, binding = 0) uniform Params { float time; vec2 resolution; vec3 domain; };".to_string()
+                }
+                "in vec2 fragTexCoord;" | "out vec4 finalColor;" => {
+                    format!("layout(location = 0) {}", line)
+                }
+                _ => match line
+                    .strip_prefix("uniform sampler2D image_")
+                    .and_then(|rest| rest.strip_suffix(';'))
+                {
+                    Some(id) => {
+                        let id: u32 = id.parse().unwrap();
+                        format!(
+                            "layout(set = 1, binding = {}) uniform texture2D image_{}_t;\n\
+                             layout(set = 1, binding = {}) uniform sampler image_{}_s;",
+                            2 * id,
+                            id,
+                            2 * id + 1,
+                            id
+                        )
+                    }
+                    None => line.to_string(),
+                },
+            };
+            glsl.push_str(&line);
+            glsl.push('\n');
+        }
+        for id in 0..3 {
+            glsl = glsl.replace(
+                &format!("texelFetch(image_{},", id),
+                &format!("texelFetch(sampler2D(image_{id}_t, image_{id}_s),"),
+            );
+        }
+        let options = naga::front::glsl::Options::from(naga::ShaderStage::Fragment);
+        match naga::front::glsl::Frontend::default().parse(&options, &glsl) {
+            Ok(module) => validate(module, &glsl),
+            Err(err) => panic!("{}\n{}", err.emit_to_string(&glsl), glsl),
+        }
+    }
+
+    /// Declares the Shadertoy inputs in Vulkan GLSL and calls `mainImage`
+    /// from `main`, as Shadertoy does.
+    fn validate_shadertoy(source: &str) {
+        let mut glsl = String::from(
+            "#version 450\n\
+             layout(set = 0, binding = 0) uniform Inputs { vec3 iResolution; float iTime; vec3 iChannelResolution[4]; };\n",
+        );
+        for id in 0..4 {
+            glsl.push_str(&format!(
+                "layout(set = 1, binding = {}) uniform texture2D iChannel{}_t;\n\
+                 layout(set = 1, binding = {}) uniform sampler iChannel{}_s;\n",
+                2 * id,
+                id,
+                2 * id + 1,
+                id
+            ));
+        }
+        let mut source = source.to_string();
+        for id in 0..4 {
+            source = source.replace(
+                &format!("texelFetch(iChannel{},", id),
+                &format!("texelFetch(sampler2D(iChannel{id}_t, iChannel{id}_s),"),
+            );
+        }
+        glsl.push_str(&source);
+        glsl.push_str(
+            "layout(location = 0) out vec4 finalColor;\n\
+             void main() {\n    mainImage(finalColor, gl_FragCoord.xy);\n}\n",
+        );
+        let options = naga::front::glsl::Options::from(naga::ShaderStage::Fragment);
+        match naga::front::glsl::Frontend::default().parse(&options, &glsl) {
+            Ok(module) => validate(module, &glsl),
+            Err(err) => panic!("{}\n{}", err.emit_to_string(&glsl), glsl),
+        }
+    }
+
+    #[test]
+    fn wgsl_shaders_validate() {
+        let (spec, images) = (RenderSpec::default(), test_images());
+        for tree in test_trees() {
+            validate_wgsl(&Wgsl.fragment_shader(&tree, &spec, &images));
+        }
+    }
+
+    #[test]
+    fn glsl_330_shaders_validate() {
+        let (spec, images) = (RenderSpec::default(), test_images());
+        for tree in test_trees() {
+            validate_glsl(&Glsl330.fragment_shader(&tree, &spec, &images));
+        }
+    }
+
+    #[test]
+    fn glsl_es_300_shaders_validate() {
+        let (spec, images) = (RenderSpec::default(), test_images());
+        for tree in test_trees() {
+            validate_glsl(&GlslEs300.fragment_shader(&tree, &spec, &images));
+        }
+    }
+
+    /// Identifiers directly followed by `(`, with whether a type name comes
+    /// right before them, which makes them a function definition.
+    fn hlsl_calls(source: &str) -> Vec<(&str, bool)> {
+        let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let mut calls = Vec::new();
+        let mut previous = "";
+        let mut rest = source;
+        while let Some(start) = rest.find(|c: char| is_ident(c)) {
+            rest = &rest[start..];
+            let end = rest.find(|c: char| !is_ident(c)).unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+            if rest.starts_with('(') && !word.starts_with(|c: char| c.is_ascii_digit()) {
+                let definition = matches!(
+                    previous,
+                    "float" | "float2" | "float3" | "float4" | "uint" | "int"
+                );
+                calls.push((word, definition));
+            }
+            previous = word;
+        }
+        calls
+    }
+
+    /// HLSL cannot be validated without DXC, so this checks the interface the
+    /// renderers bind, that every called function is an intrinsic or defined
+    /// in the shader, and that no literal is infinite or NaN.
+    fn check_hlsl(source: &str, root: &Operation, images: &ImageSet) {
+        const INTRINSICS: &[&str] = &[
+            "abs", "asuint", "atan2", "clamp", "cos", "distance", "dot", "exp", "float", "float2",
+            "float3", "float4", "floor", "frac", "int2", "int3", "length", "lerp", "log", "log2",
+            "max", "min", "pow", "register", "saturate", "sin", "sqrt", "tan", "uint", "uint2",
+            "Load",
+        ];
+        assert!(
+            source.starts_with("cbuffer Uniforms : register(b0) {"),
+            "{}",
+            source
+        );
+        assert!(source.contains("float4 main(float4 position : SV_Position) : SV_Target {"));
+        for id in 0..images.sampled_count(root) {
+            let texture = format!("Texture2D<float4> image_{} : register(t{});", id, id);
+            assert_eq!(
+                source.contains(&texture),
+                id < images.images().len(),
+                "{}\n{}",
+                texture,
+                source
+            );
+            assert!(source.contains(&format!("float3 sample_image_{}(", id)));
+        }
+        let calls = hlsl_calls(source);
+        let undefined: Vec<&str> = calls
+            .iter()
+            .map(|&(name, _)| name)
+            .filter(|name| !INTRINSICS.contains(name) && !calls.contains(&(name, true)))
+            .collect();
+        assert!(
+            undefined.is_empty(),
+            "{:?} not defined\n{}",
+            undefined,
+            source
+        );
+        let words: Vec<&str> = source
+            .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .collect();
+        assert!(
+            !words.contains(&"inf") && !words.contains(&"NaN"),
+            "{}",
+            source
+        );
+    }
+
+    #[test]
+    fn hlsl_shaders_are_well_formed() {
+        let (spec, images) = (RenderSpec::default(), test_images());
+        for tree in test_trees() {
+            check_hlsl(&Hlsl.fragment_shader(&tree, &spec, &images), &tree, &images);
+        }
+    }
+
+    #[test]
+    fn shadertoy_shaders_validate() {
+        let (spec, images) = (RenderSpec::default(), test_images());
+        for tree in test_trees() {
+            validate_shadertoy(&Shadertoy.fragment_shader(&tree, &spec, &images));
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use crate::images::{ImageSet, WrapMode};
+use crate::operations::Operation;
+use crate::renderer::{ColorMapping, RenderSpec, TimeMapping};
+use crate::shader::{float_literal, tree_functions, ShaderBackend};
+
+/// WGSL, for WebGPU. The uniforms are a `Uniforms` struct at group 0,
+/// binding 0, and image `id` is a `texture_2d<f32>` at binding `id + 1`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wgsl;
+
+impl ShaderBackend for Wgsl {
+    fn fragment_shader(&self, root: &Operation, spec: &RenderSpec, images: &ImageSet) -> String {
+        let (name, functions) = tree_functions(self, root);
+        let map_color = match spec.color {
+            ColorMapping::Signed => {
+                "return vec4<f32>(clamp((rgb + 1.0) / 2.0, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);"
+            }
+            ColorMapping::Clamp => {
+                "return vec4<f32>(clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);"
+            }
+        };
+        let time = match spec.time {
+            TimeMapping::Fixed(t) => float_literal(t),
+            TimeMapping::Sine => "sin(uniforms.time)".to_string(),
+        };
+        FRAGMENT_SHADER
+            .replace("#MAP_COLOR#", map_color)
+            .replace("#HELPERS#", HELPERS)
+            .replace("#NOISE_FUNCTIONS#", NOISE_FUNCTIONS)
+            .replace("#IMAGE_FUNCTIONS#", &image_functions(images, root))
+            .replace("#FUNCTIONS#", &functions)
+            .replace("#TIME#", &time)
+            .replace("#ROOT#", &name)
+    }
+
+    fn expression(&self, op: &Operation, children: &[String], function: Option<&str>) -> String {
+        let child = |i: usize| children[i].as_str();
+        let call = |point: &str| format!("{}({}, t)", function.unwrap_or_default(), point);
+        let splat = |value: f64| format!("vec3<f32>({})", float_literal(value));
+        match op {
+            Operation::Sum(..) => format!("({} + {})", child(0), child(1)),
+            Operation::Product(..) => format!("({} * {})", child(0), child(1)),
+            Operation::Mod(..) => format!("mod_fn({}, {})", child(0), child(1)),
+            Operation::Constant(value) => splat(*value),
+            Operation::VarX => "vec3<f32>(x)".to_string(),
+            Operation::VarY => "vec3<f32>(y)".to_string(),
+            Operation::VarT => "vec3<f32>(t)".to_string(),
+            Operation::Circle(cx, cy) => format!(
+                "vec3<f32>(distance(vec2<f32>(x, y), vec2<f32>({}, {})))",
+                float_literal(*cx),
+                float_literal(*cy)
+            ),
+            Operation::Inverse(..) => format!("(vec3<f32>(0.0) - {})", child(0)),
+            Operation::PerChannelMask(.., threshold) => format!(
+                "select({}, {}, {} > {})",
+                child(2),
+                child(1),
+                child(0),
+                splat(*threshold)
+            ),
+            Operation::Sine(..) => format!("sin({})", child(0)),
+            Operation::BinaryMask(.., threshold) => format!(
+                "select({}, {}, length({}) > {})",
+                child(2),
+                child(1),
+                child(0),
+                float_literal(*threshold)
+            ),
+            Operation::SmoothMix(..) => format!(
+                "(({} * {}) + ((1.0 - {}) * {}))",
+                child(0),
+                child(1),
+                child(0),
+                child(2)
+            ),
+            Operation::Well(..) => format!("well_fn({})", child(0)),
+            Operation::Tent(..) => format!("tent_fn({})", child(0)),
+            Operation::RGB(..) => {
+                format!("vec3<f32>({}.x, {}.y, {}.z)", child(0), child(1), child(2))
+            }
+            Operation::Cos(..) => format!("cos({})", child(0)),
+            Operation::Tan(..) => format!("tan_fn({})", child(0)),
+            Operation::Atan2(..) => format!("atan2_fn({}, {})", child(0), child(1)),
+            Operation::Exp(..) => format!("exp_fn({})", child(0)),
+            Operation::Log(..) => format!("log_fn({})", child(0)),
+            Operation::Pow(..) => format!("pow_fn({}, {})", child(0), child(1)),
+            Operation::Abs(..) => format!("abs({})", child(0)),
+            Operation::Sqrt(..) => format!("sqrt(abs({}))", child(0)),
+            Operation::Min(..) => format!("min({}, {})", child(0), child(1)),
+            Operation::Max(..) => format!("max({}, {})", child(0), child(1)),
+            Operation::Clamp(_, low, high) => {
+                // min(max()) rather than clamp(), which is an error for low > high
+                format!("min(max({}, {}), {})", child(0), splat(*low), splat(*high))
+            }
+            Operation::Perlin(.., seed) => {
+                format!("perlin_fn({}, {}, {}u)", child(0), child(1), seed)
+            }
+            Operation::Simplex(.., seed) => {
+                format!("simplex_fn({}, {}, {}u)", child(0), child(1), seed)
+            }
+            Operation::ValueNoise(.., seed) => {
+                format!("value_noise_fn({}, {}, {}u)", child(0), child(1), seed)
+            }
+            Operation::Worley(.., seed) => {
+                format!("worley_fn({}, {}, {}u)", child(0), child(1), seed)
+            }
+            Operation::Fbm(_, _, seed, octaves) => format!(
+                "fbm_fn({}, {}, {}u, {}u)",
+                child(0),
+                child(1),
+                seed,
+                octaves
+            ),
+            Operation::Mandelbrot(..) => format!("mandelbrot_fn({}, {})", child(0), child(1)),
+            Operation::Julia(_, _, c_re, c_im) => format!(
+                "julia_fn({}, {}, {}, {})",
+                child(0),
+                child(1),
+                float_literal(*c_re),
+                float_literal(*c_im)
+            ),
+            Operation::Rotate(angle, _) => {
+                let (sin, cos) = angle.sin_cos();
+                let (sin, cos) = (float_literal(sin), float_literal(cos));
+                call(&format!(
+                    "vec2<f32>(x * {cos} + y * {sin}, y * {cos} - x * {sin})"
+                ))
+            }
+            Operation::Scale(sx, sy, _) => call(&format!(
+                "vec2<f32>(x * {}, y * {})",
+                float_literal(*sx),
+                float_literal(*sy)
+            )),
+            Operation::Translate(dx, dy, _) => call(&format!(
+                "vec2<f32>(x - {}, y - {})",
+                float_literal(*dx),
+                float_literal(*dy)
+            )),
+            Operation::Polar(_) => call("vec2<f32>(length(vec2<f32>(x, y)), atan2_scalar(y, x))"),
+            Operation::Warp(..) => call(&format!(
+                "vec2<f32>(x + mean_fn({}), y + mean_fn({}))",
+                child(0),
+                child(1)
+            )),
+            Operation::Mirror(_) => call("vec2<f32>(abs(x), y)"),
+            Operation::Kaleidoscope(n, _) => call(&format!("kaleidoscope_fn(x, y, {}.0)", n)),
+            Operation::Image(id) => format!("sample_image_{}(x, y)", id),
+        }
+    }
+
+    fn variable(&self, name: &str, expression: &str) -> String {
+        format!("    let {} = {};\n", name, expression)
+    }
+
+    fn function(&self, name: &str, statements: &str, result: &str) -> String {
+        format!(
+            "fn {}(xy: vec2<f32>, t: f32) -> vec3<f32> {{\n    let x = xy.x;\n    let y = xy.y;\n{}    return {};\n}}\n\n",
+            name, statements, result
+        )
+    }
+}
+
+fn image_functions(images: &ImageSet, root: &Operation) -> String {
+    let mut wgsl = String::new();
+    for id in 0..images.sampled_count(root) {
+        match images.images().get(id) {
+            Some(image) => {
+                let size = format!(
+                    "vec2<f32>({}.0, {}.0)",
+                    image.image().width(),
+                    image.image().height()
+                );
+                let wrap = match image.wrap() {
+                    WrapMode::Repeat => "cell - size * floor(cell / size)",
+                    WrapMode::Clamp => "clamp(cell, vec2<f32>(0.0), size - 1.0)",
+                };
+                wgsl.push_str(
+                    &SAMPLE_IMAGE
+                        .replace("#ID#", &id.to_string())
+                        .replace("#BINDING#", &(id + 1).to_string())
+                        .replace("#SIZE#", &size)
+                        .replace("#WRAP#", wrap),
+                );
+            }
+            None => wgsl.push_str(&format!(
+                "fn sample_image_{id}(x: f32, y: f32) -> vec3<f32> {{\n    return vec3<f32>(0.0);\n}}\n"
+            )),
+        }
+    }
+    wgsl
+}
+
+const FRAGMENT_SHADER: &str = r#"struct Uniforms {
+    time: f32,
+    resolution: vec2<f32>,
+    // center x, center y, half extent of the shorter side
+    domain: vec3<f32>,
+}
+
+@group(0) @binding(0) var<uniform> uniforms: Uniforms;
+
+fn map_color(rgb: vec3<f32>) -> vec4<f32> {
+    #MAP_COLOR#
+}
+#HELPERS#
+#NOISE_FUNCTIONS#
+#IMAGE_FUNCTIONS#
+#FUNCTIONS#
+@fragment
+fn main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // Same mapping as RenderSpec::pixel_to_point; fragment coordinates start
+    // at the top, like image rows
+    let resolution = uniforms.resolution;
+    let domain = uniforms.domain;
+    let scale = 2.0 * domain.z / min(resolution.x, resolution.y);
+    let x = domain.x + (position.x - resolution.x / 2.0) * scale;
+    let y = domain.y + (position.y - resolution.y / 2.0) * scale;
+    let t = #TIME#;
+    return map_color(#ROOT#(vec2<f32>(x, y), t));
+}
+"#;
+
+const HELPERS: &str = r#"
+fn mod_fn(x: vec3<f32>, y: vec3<f32>) -> vec3<f32> {
+    // Like f64::rem_euclid, never negative; % follows the sign of x
+    return x - abs(y) * floor(x / abs(y));
+}
+
+fn well_fn(x: vec3<f32>) -> vec3<f32> {
+    // pow() is undefined for negative bases
+    var w = 1.0 - 2.0 / (1.0 + x * x);
+    w *= w;
+    w *= w;
+    return w * w;
+}
+
+fn tent_fn(x: vec3<f32>) -> vec3<f32> {
+    return 1.0 - 2.0 * abs(x);
+}
+
+// Keep in sync with Operation::escape_time_fn, FRACTAL_ITERATIONS and ESCAPE_RADIUS
+fn escape_time_fn(z_re_start: f32, z_im_start: f32, c_re: f32, c_im: f32) -> f32 {
+    var z_re = z_re_start;
+    var z_im = z_im_start;
+    for (var n = 0; n < 64; n++) {
+        let re2 = z_re * z_re;
+        let im2 = z_im * z_im;
+        if (re2 + im2 > 256.0 * 256.0) {
+            let smooth_n = f32(n) + 1.0 - log2(log(re2 + im2) / 2.0);
+            return 2.0 * clamp(smooth_n, 0.0, 64.0) / 64.0 - 1.0;
+        }
+        z_im = 2.0 * z_re * z_im + c_im;
+        z_re = re2 - im2 + c_re;
+    }
+    return 1.0;
+}
+
+fn mandelbrot_fn(re: vec3<f32>, im: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(escape_time_fn(0.0, 0.0, re.x, im.x), escape_time_fn(0.0, 0.0, re.y, im.y),
+                     escape_time_fn(0.0, 0.0, re.z, im.z));
+}
+
+fn julia_fn(re: vec3<f32>, im: vec3<f32>, c_re: f32, c_im: f32) -> vec3<f32> {
+    return vec3<f32>(escape_time_fn(re.x, im.x, c_re, c_im), escape_time_fn(re.y, im.y, c_re, c_im),
+                     escape_time_fn(re.z, im.z, c_re, c_im));
+}
+
+// Keep in sync with TAN_LIMIT, EXP_LIMIT and EPSILON in operations.rs
+fn tan_fn(x: vec3<f32>) -> vec3<f32> {
+    return clamp(tan(x), vec3<f32>(-10.0), vec3<f32>(10.0));
+}
+
+fn atan2_scalar(y: f32, x: f32) -> f32 {
+    // Return 0 for atan2(0, 0) like Rust
+    return select(atan2(y, x) / 3.14159265358979, 0.0, x == 0.0 && y == 0.0);
+}
+
+fn atan2_fn(y: vec3<f32>, x: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(atan2_scalar(y.x, x.x), atan2_scalar(y.y, x.y), atan2_scalar(y.z, x.z));
+}
+
+fn exp_fn(x: vec3<f32>) -> vec3<f32> {
+    return exp(min(x, vec3<f32>(10.0)));
+}
+
+fn log_fn(x: vec3<f32>) -> vec3<f32> {
+    return log(max(abs(x), vec3<f32>(1e-6)));
+}
+
+fn pow_fn(x: vec3<f32>, y: vec3<f32>) -> vec3<f32> {
+    return pow(max(abs(x), vec3<f32>(1e-6)), y);
+}
+
+fn mean_fn(v: vec3<f32>) -> f32 {
+    return (v.x + v.y + v.z) / 3.0;
+}
+
+fn kaleidoscope_fn(x: f32, y: f32, n: f32) -> vec2<f32> {
+    let wedge = 6.28318530717959 / max(n, 1.0);
+    let a = atan2_scalar(y, x) * 3.14159265358979;
+    let angle = abs(a - wedge * floor(a / wedge) - wedge / 2.0);
+    let r = length(vec2<f32>(x, y));
+    return vec2<f32>(r * cos(angle), r * sin(angle));
+}
+"#;
+
+const NOISE_FUNCTIONS: &str = r#"
+// Keep in sync with noise.rs
+const NOISE_SCALE: f32 = 4.0;
+const NOISE_LIMIT: f32 = 4096.0;
+const MAX_OCTAVES: u32 = 8u;
+
+fn noise_hash(value: u32) -> u32 {
+    var h = value;
+    h ^= h >> 16u;
+    h *= 0x7feb352du;
+    h ^= h >> 15u;
+    h *= 0x846ca68bu;
+    h ^= h >> 16u;
+    return h;
+}
+
+fn hash_cell(cell: vec2<i32>, seed: u32) -> u32 {
+    return noise_hash(u32(cell.x) ^ noise_hash(u32(cell.y) ^ noise_hash(seed)));
+}
+
+fn noise_unit(h: u32) -> f32 {
+    return f32(h) / 4294967296.0;
+}
+
+fn noise_prepare(x: f32, y: f32) -> vec2<f32> {
+    return clamp(vec2<f32>(x, y) * NOISE_SCALE, vec2<f32>(-NOISE_LIMIT), vec2<f32>(NOISE_LIMIT));
+}
+
+fn noise_fade(t: vec2<f32>) -> vec2<f32> {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn noise_gradient(h: u32, d: vec2<f32>) -> f32 {
+    var gradients = array<vec2<f32>, 8>(
+        vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0), vec2<f32>(1.0, -1.0), vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 0.0), vec2<f32>(-1.0, 0.0), vec2<f32>(0.0, 1.0), vec2<f32>(0.0, -1.0)
+    );
+    return dot(gradients[h & 7u], d);
+}
+
+fn perlin_scalar(x: f32, y: f32, seed: u32) -> f32 {
+    let p = noise_prepare(x, y);
+    let p0 = floor(p);
+    let f = p - p0;
+    let i = vec2<i32>(p0);
+    let n00 = noise_gradient(hash_cell(i, seed), f);
+    let n10 = noise_gradient(hash_cell(i + vec2<i32>(1, 0), seed), f - vec2<f32>(1.0, 0.0));
+    let n01 = noise_gradient(hash_cell(i + vec2<i32>(0, 1), seed), f - vec2<f32>(0.0, 1.0));
+    let n11 = noise_gradient(hash_cell(i + vec2<i32>(1, 1), seed), f - vec2<f32>(1.0, 1.0));
+    let u = noise_fade(f);
+    return mix(mix(n00, n10, u.x), mix(n01, n11, u.x), u.y);
+}
+
+fn value_noise_scalar(x: f32, y: f32, seed: u32) -> f32 {
+    let p = noise_prepare(x, y);
+    let p0 = floor(p);
+    let f = p - p0;
+    let i = vec2<i32>(p0);
+    let n00 = 2.0 * noise_unit(hash_cell(i, seed)) - 1.0;
+    let n10 = 2.0 * noise_unit(hash_cell(i + vec2<i32>(1, 0), seed)) - 1.0;
+    let n01 = 2.0 * noise_unit(hash_cell(i + vec2<i32>(0, 1), seed)) - 1.0;
+    let n11 = 2.0 * noise_unit(hash_cell(i + vec2<i32>(1, 1), seed)) - 1.0;
+    let u = noise_fade(f);
+    return mix(mix(n00, n10, u.x), mix(n01, n11, u.x), u.y);
+}
+
+fn simplex_corner(cell: vec2<i32>, seed: u32, d: vec2<f32>) -> f32 {
+    var falloff = 0.5 - dot(d, d);
+    if (falloff < 0.0) {
+        return 0.0;
+    }
+    falloff *= falloff;
+    return falloff * falloff * noise_gradient(hash_cell(cell, seed), d);
+}
+
+fn simplex_scalar(x: f32, y: f32, seed: u32) -> f32 {
+    let F2 = 0.3660254037844386;
+    let G2 = 0.2113248654051871;
+    let p = noise_prepare(x, y);
+    let ij = floor(p + (p.x + p.y) * F2);
+    let d0 = p - (ij - (ij.x + ij.y) * G2);
+    let o = select(vec2<i32>(0, 1), vec2<i32>(1, 0), d0.x > d0.y);
+    let i = vec2<i32>(ij);
+    let n0 = simplex_corner(i, seed, d0);
+    let n1 = simplex_corner(i + o, seed, d0 - vec2<f32>(o) + G2);
+    let n2 = simplex_corner(i + vec2<i32>(1, 1), seed, d0 - 1.0 + 2.0 * G2);
+    return 70.0 * (n0 + n1 + n2);
+}
+
+fn worley_scalar(x: f32, y: f32, seed: u32) -> f32 {
+    let p = noise_prepare(x, y);
+    let p0 = floor(p);
+    let f = p - p0;
+    let i = vec2<i32>(p0);
+    var closest = 1.0;
+    for (var j = -1; j <= 1; j++) {
+        for (var k = -1; k <= 1; k++) {
+            let h = hash_cell(i + vec2<i32>(k, j), seed);
+            let d = vec2<f32>(f32(k), f32(j)) + vec2<f32>(noise_unit(h), noise_unit(noise_hash(h))) - f;
+            closest = min(closest, sqrt(dot(d, d)));
+        }
+    }
+    return 2.0 * closest - 1.0;
+}
+
+fn fbm_scalar(x: f32, y: f32, seed: u32, octaves: u32) -> f32 {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var octave = 0u; octave < clamp(octaves, 1u, MAX_OCTAVES); octave++) {
+        sum += amplitude * perlin_scalar(x * frequency, y * frequency, seed + octave);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return sum / total;
+}
+
+fn perlin_fn(x: vec3<f32>, y: vec3<f32>, seed: u32) -> vec3<f32> {
+    return vec3<f32>(perlin_scalar(x.x, y.x, seed), perlin_scalar(x.y, y.y, seed), perlin_scalar(x.z, y.z, seed));
+}
+
+fn value_noise_fn(x: vec3<f32>, y: vec3<f32>, seed: u32) -> vec3<f32> {
+    return vec3<f32>(value_noise_scalar(x.x, y.x, seed), value_noise_scalar(x.y, y.y, seed),
+                     value_noise_scalar(x.z, y.z, seed));
+}
+
+fn simplex_fn(x: vec3<f32>, y: vec3<f32>, seed: u32) -> vec3<f32> {
+    return vec3<f32>(simplex_scalar(x.x, y.x, seed), simplex_scalar(x.y, y.y, seed), simplex_scalar(x.z, y.z, seed));
+}
+
+fn worley_fn(x: vec3<f32>, y: vec3<f32>, seed: u32) -> vec3<f32> {
+    return vec3<f32>(worley_scalar(x.x, y.x, seed), worley_scalar(x.y, y.y, seed), worley_scalar(x.z, y.z, seed));
+}
+
+fn fbm_fn(x: vec3<f32>, y: vec3<f32>, seed: u32, octaves: u32) -> vec3<f32> {
+    return vec3<f32>(fbm_scalar(x.x, y.x, seed, octaves), fbm_scalar(x.y, y.y, seed, octaves),
+                     fbm_scalar(x.z, y.z, seed, octaves));
+}
+"#;
+
+// Keep in sync with InputImage::sample
+const SAMPLE_IMAGE: &str = r#"
+@group(0) @binding(#BINDING#) var image_#ID#: texture_2d<f32>;
+
+fn image_texel_#ID#(texel: vec2<f32>) -> vec3<f32> {
+    let size = #SIZE#;
+    let cell = texel;
+    let wrapped = #WRAP#;
+    return textureLoad(image_#ID#, vec2<i32>(wrapped), 0).rgb * 2.0 - 1.0;
+}
+
+fn sample_image_#ID#(x: f32, y: f32) -> vec3<f32> {
+    let p = clamp((vec2<f32>(x, y) + 1.0) / 2.0 * #SIZE# - 0.5, vec2<f32>(-16777216.0), vec2<f32>(16777216.0));
+    let p0 = floor(p);
+    let f = p - p0;
+    let top = mix(image_texel_#ID#(p0), image_texel_#ID#(p0 + vec2<f32>(1.0, 0.0)), f.x);
+    let bottom = mix(image_texel_#ID#(p0 + vec2<f32>(0.0, 1.0)), image_texel_#ID#(p0 + vec2<f32>(1.0, 1.0)), f.x);
+    return mix(top, bottom, f.y);
+}
+"#;
@@ -0,0 +1,408 @@
+use crate::operations::{Operation, EPSILON, EXP_LIMIT, TAN_LIMIT};
+use crate::vec3::Vec3;
+
+/// Largest magnitude of `x`, `y` and `t` assumed by rewrites that drop a
+/// subtree, such as multiplying it by zero. Rendered coordinates stay far below it.
+pub const INPUT_LIMIT: f64 = 1e6;
+
+/// Bound on the magnitude of the noise functions for finite inputs.
+const NOISE_BOUND: f64 = 16.0;
+
+/// Bounds above this are treated as possibly overflowing.
+const BOUND_LIMIT: f64 = 1e300;
+
+impl Operation {
+    /// Returns an equivalent tree with constant subtrees folded into
+    /// constants and redundant nodes removed, e.g. `Inverse(Inverse(a))`,
+    /// products by zero or one, masks with identical branches, and coordinate
+    /// transforms of subtrees that do not depend on the coordinates.
+    ///
+    /// The simplified tree evaluates to the same values as the original for
+    /// finite `x`, `y` and `t` up to [`INPUT_LIMIT`], except that the sign of
+    /// zero results may differ. Subtrees are only dropped when they are known to
+    /// be finite, so NaN and infinite values are preserved.
+    pub fn simplify(&self) -> Operation {
+        self.simplify_within(INPUT_LIMIT)
+    }
+
+    /// Simplifies the tree where `|x|` and `|y|` are at most `coords`, which is
+    /// infinite when no bound is known.
+    fn simplify_within(&self, coords: f64) -> Operation {
+        let children = self.children();
+        let last = children.len().saturating_sub(1);
+        let inner = self.inner_coords(coords);
+        let children = children
+            .into_iter()
+            .enumerate()
+            .map(|(i, child)| {
+                if i == last {
+                    child.simplify_within(inner)
+                } else {
+                    child.simplify_within(coords)
+                }
+            })
+            .collect();
+        let node = self.with_children(children).rewrite(coords);
+        node.fold().unwrap_or(node)
+    }
+
+    /// Applies the rewrites to a node whose children are already simplified.
+    fn rewrite(self, coords: f64) -> Operation {
+        match self {
+            Operation::Rotate(_, a)
+            | Operation::Scale(_, _, a)
+            | Operation::Translate(_, _, a)
+            | Operation::Polar(a)
+            | Operation::Warp(_, _, a)
+            | Operation::Mirror(a)
+            | Operation::Kaleidoscope(_, a)
+                if !a.uses_coordinates() =>
+            {
+                *a
+            }
+            Operation::Scale(sx, sy, a) if sx == 1.0 && sy == 1.0 => *a,
+            Operation::Translate(dx, dy, a) if dx == 0.0 && dy == 0.0 => *a,
+            Operation::Mirror(a) if matches!(*a, Operation::Mirror(_)) => *a,
+            // x - (-d) is x + d exactly
+            Operation::Warp(dx, dy, a) => match (dx.literal(), dy.literal()) {
+                (Some(dx), Some(dy)) => {
+                    Operation::Translate(-Self::mean_fn(dx), -Self::mean_fn(dy), a).rewrite(coords)
+                }
+                _ => Operation::Warp(dx, dy, a),
+            },
+
+            Operation::Inverse(a) => match *a {
+                Operation::Inverse(a) => *a,
+                a => Operation::Inverse(Box::new(a)),
+            },
+            Operation::Abs(a) => Operation::Abs(Box::new(a.without_sign())),
+            Operation::Sqrt(a) => Operation::Sqrt(Box::new(a.without_sign())),
+            Operation::Log(a) => Operation::Log(Box::new(a.without_sign())),
+            Operation::Pow(a, b) => Operation::Pow(Box::new(a.without_sign()), b),
+            Operation::Well(a) => Operation::Well(Box::new(a.without_sign())),
+            Operation::Tent(a) => Operation::Tent(Box::new(a.without_sign())),
+            Operation::Min(a, b) | Operation::Max(a, b) if a == b => *a,
+
+            Operation::Sum(a, b) if b.literal().is_some_and(is_zero) => *a,
+            Operation::Sum(a, b) if a.literal().is_some_and(is_zero) => *b,
+            Operation::Product(a, b) if b.literal().is_some_and(is_one) => *a,
+            Operation::Product(a, b) if a.literal().is_some_and(is_one) => *b,
+            Operation::Product(a, b)
+                if (a.literal().is_some_and(is_zero) && b.bound(coords).is_some())
+                    || (b.literal().is_some_and(is_zero) && a.bound(coords).is_some()) =>
+            {
+                Operation::Constant(0.0)
+            }
+
+            Operation::BinaryMask(_, a, b, _) if a == b => *a,
+            Operation::BinaryMask(m, a, b, threshold) => match m.literal() {
+                Some(m) if m.length() > threshold => *a,
+                Some(_) => *b,
+                None => Operation::BinaryMask(m, a, b, threshold),
+            },
+            Operation::PerChannelMask(_, a, b, _) if a == b => *a,
+            Operation::PerChannelMask(m, a, b, threshold) => match m.literal() {
+                Some(m) => {
+                    let pick = |v: f64| if v > threshold { a.clone() } else { b.clone() };
+                    Operation::RGB(pick(m.x()), pick(m.y()), pick(m.z())).rewrite(coords)
+                }
+                None => Operation::PerChannelMask(m, a, b, threshold),
+            },
+            Operation::RGB(r, g, b) => {
+                // Only one channel of each argument is used
+                let r = match *r {
+                    Operation::RGB(r, _, _) => r,
+                    r => Box::new(r),
+                };
+                let g = match *g {
+                    Operation::RGB(_, g, _) => g,
+                    g => Box::new(g),
+                };
+                let b = match *b {
+                    Operation::RGB(_, _, b) => b,
+                    b => Box::new(b),
+                };
+                if r == g && g == b {
+                    *r
+                } else {
+                    Operation::RGB(r, g, b)
+                }
+            }
+            node => node,
+        }
+    }
+
+    /// Evaluates a node whose children are all constant, if its value is finite.
+    fn fold(&self) -> Option<Operation> {
+        let children = self.children();
+        if children.is_empty() || !children.iter().all(|child| child.literal().is_some()) {
+            return None;
+        }
+        let value = self.eval(0.0, 0.0, 0.0);
+        if !(value.x().is_finite() && value.y().is_finite() && value.z().is_finite()) {
+            return None;
+        }
+        if value.x().to_bits() == value.y().to_bits() && value.y().to_bits() == value.z().to_bits()
+        {
+            Some(Operation::Constant(value.x()))
+        } else {
+            Some(Operation::RGB(
+                Box::new(Operation::Constant(value.x())),
+                Box::new(Operation::Constant(value.y())),
+                Box::new(Operation::Constant(value.z())),
+            ))
+        }
+    }
+
+    /// The value of a constant or an `RGB` of constants.
+    fn literal(&self) -> Option<Vec3> {
+        match self {
+            Operation::Constant(value) => Some(Vec3::new(*value, *value, *value)),
+            Operation::RGB(r, g, b) => match (r.as_ref(), g.as_ref(), b.as_ref()) {
+                (Operation::Constant(r), Operation::Constant(g), Operation::Constant(b)) => {
+                    Some(Vec3::new(*r, *g, *b))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Strips negations and absolute values from the argument of a function
+    /// that only depends on its magnitude.
+    fn without_sign(self) -> Operation {
+        match self {
+            Operation::Inverse(a) | Operation::Abs(a) => a.without_sign(),
+            a => a,
+        }
+    }
+
+    /// Whether the value depends on `x` or `y`.
+    fn uses_coordinates(&self) -> bool {
+        match self {
+            Operation::VarX | Operation::VarY | Operation::Circle(..) | Operation::Image(_) => true,
+            Operation::Rotate(_, a)
+            | Operation::Scale(_, _, a)
+            | Operation::Translate(_, _, a)
+            | Operation::Polar(a)
+            | Operation::Warp(_, _, a)
+            | Operation::Mirror(a)
+            | Operation::Kaleidoscope(_, a) => a.uses_coordinates(),
+            op => op.children().iter().any(|child| child.uses_coordinates()),
+        }
+    }
+
+    /// Bound on the coordinates seen by the last child, given the bound
+    /// `coords` of this node's coordinates.
+    fn inner_coords(&self, coords: f64) -> f64 {
+        if !coords.is_finite() {
+            return f64::INFINITY;
+        }
+        let inner = match self {
+            Operation::Rotate(..) | Operation::Kaleidoscope(..) => Some(2.0 * coords),
+            Operation::Scale(sx, sy, _) => Some(coords * sx.abs().max(sy.abs())),
+            Operation::Translate(dx, dy, _) => Some(coords + dx.abs().max(dy.abs())),
+            Operation::Polar(_) => Some((2.0 * coords).max(1.0)),
+            Operation::Warp(dx, dy, _) => dx
+                .bound(coords)
+                .zip(dy.bound(coords))
+                .map(|(dx, dy)| coords + dx.max(dy)),
+            _ => Some(coords),
+        };
+        inner.and_then(finite).unwrap_or(f64::INFINITY)
+    }
+
+    /// Upper bound on the magnitude of every channel where `|x|` and `|y|` are
+    /// at most `coords` and `|t|` is at most [`INPUT_LIMIT`], or `None` if the
+    /// value may not be finite.
+    fn bound(&self, coords: f64) -> Option<f64> {
+        match self {
+            Operation::Constant(value) => finite(value.abs()),
+            Operation::VarX | Operation::VarY => finite(coords),
+            Operation::VarT => Some(INPUT_LIMIT),
+            Operation::Circle(cx, cy) => finite(2.0 * (coords + cx.abs() + cy.abs())),
+            Operation::Image(_) => finite(coords).map(|_| 1.0),
+            Operation::Sum(a, b) => finite(a.bound(coords)? + b.bound(coords)?),
+            Operation::Product(a, b) => finite(a.bound(coords)? * b.bound(coords)?),
+            Operation::Mod(a, b) => {
+                a.bound(coords)?;
+                let b = b.literal()?;
+                let nonzero = |v: f64| v != 0.0 && v.is_finite();
+                (nonzero(b.x()) && nonzero(b.y()) && nonzero(b.z()))
+                    .then(|| b.x().abs().max(b.y().abs()).max(b.z().abs()))
+            }
+            Operation::Inverse(a) | Operation::Abs(a) => a.bound(coords),
+            Operation::Sine(a) | Operation::Cos(a) | Operation::Well(a) => {
+                a.bound(coords).map(|_| 1.0)
+            }
+            Operation::Tan(a) => a.bound(coords).map(|_| TAN_LIMIT),
+            Operation::Exp(a) => a.bound(coords).map(|_| EXP_LIMIT.exp()),
+            Operation::Log(a) => a.bound(coords).map(|a| a.max(1.0).ln().max(-EPSILON.ln())),
+            Operation::Sqrt(a) => a.bound(coords).map(f64::sqrt),
+            Operation::Tent(a) => finite(1.0 + 2.0 * a.bound(coords)?),
+            Operation::Atan2(a, b) => a.bound(coords).and(b.bound(coords)).map(|_| 1.0),
+            Operation::Pow(a, b) => {
+                finite(a.bound(coords)?.max(1.0 / EPSILON).powf(b.bound(coords)?))
+            }
+            Operation::Min(a, b) | Operation::Max(a, b) => {
+                Some(a.bound(coords)?.max(b.bound(coords)?))
+            }
+            // f64::max ignores NaN, so the result is always within the bounds
+            Operation::Clamp(_, low, high) => finite(low.abs().max(high.abs())),
+            Operation::PerChannelMask(_, a, b, _) | Operation::BinaryMask(_, a, b, _) => {
+                Some(a.bound(coords)?.max(b.bound(coords)?))
+            }
+            Operation::SmoothMix(weight, a, b) => {
+                let weight = 2.0 * weight.bound(coords)?;
+                finite(weight * a.bound(coords)? + (1.0 + weight) * b.bound(coords)?)
+            }
+            Operation::RGB(r, g, b) => {
+                Some(r.bound(coords)?.max(g.bound(coords)?).max(b.bound(coords)?))
+            }
+            Operation::Perlin(a, b, _)
+            | Operation::Simplex(a, b, _)
+            | Operation::ValueNoise(a, b, _)
+            | Operation::Worley(a, b, _)
+            | Operation::Fbm(a, b, ..) => a.bound(coords).and(b.bound(coords)).map(|_| NOISE_BOUND),
+            // Escape times are clamped, and points that never escape,
+            // including NaN, evaluate to 1
+            Operation::Mandelbrot(..) | Operation::Julia(..) => Some(1.0),
+            Operation::Rotate(_, a)
+            | Operation::Scale(_, _, a)
+            | Operation::Translate(_, _, a)
+            | Operation::Polar(a)
+            | Operation::Warp(_, _, a)
+            | Operation::Mirror(a)
+            | Operation::Kaleidoscope(_, a) => a.bound(self.inner_coords(coords)),
+        }
+    }
+}
+
+fn finite(bound: f64) -> Option<f64> {
+    (bound <= BOUND_LIMIT).then_some(bound)
+}
+
+fn is_zero(v: Vec3) -> bool {
+    v.x() == 0.0 && v.y() == 0.0 && v.z() == 0.0
+}
+
+fn is_one(v: Vec3) -> bool {
+    v.x() == 1.0 && v.y() == 1.0 && v.z() == 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::{ArtGrammar, OperationWeights, PerrigSongGrammar, RandomArtGrammar};
+    use crate::seed::Seed;
+
+    /// Equal up to the sign of zero, with NaN equal to itself.
+    fn same(a: f64, b: f64) -> bool {
+        a == b || (a.is_nan() && b.is_nan())
+    }
+
+    fn assert_same_values(tree: &Operation, simplified: &Operation) {
+        for i in 0..40 {
+            let i = i as f64;
+            let (x, y, t) = (
+                3.0 * (i * 0.71).sin(),
+                3.0 * (i * 0.43).cos(),
+                (i * 0.29).sin(),
+            );
+            let (a, b) = (tree.eval(x, y, t), simplified.eval(x, y, t));
+            assert!(
+                same(a.x(), b.x()) && same(a.y(), b.y()) && same(a.z(), b.z()),
+                "{:?} != {:?} at ({}, {}, {}) for {} simplified to {}",
+                a,
+                b,
+                x,
+                y,
+                t,
+                tree,
+                simplified
+            );
+        }
+    }
+
+    fn assert_simplifies(tree: &Operation, expected: &Operation) {
+        let simplified = tree.simplify();
+        assert_eq!(&simplified, expected, "simplifying {}", tree);
+        assert_same_values(tree, &simplified);
+    }
+
+    #[test]
+    fn random_trees_keep_their_values() {
+        for i in 0..200 {
+            let seed = Seed::from_string(&format!("simplify {}", i));
+            let depth = i % 8;
+            let trees = [
+                RandomArtGrammar::from_seed(&seed)
+                    .with_weights(OperationWeights::uniform())
+                    .generate_tree(depth),
+                PerrigSongGrammar::from_seed(&seed)
+                    .with_weights(OperationWeights::uniform())
+                    .generate_tree(depth),
+                RandomArtGrammar::from_seed(&seed).generate_tree(depth),
+                PerrigSongGrammar::from_seed(&seed).generate_tree(depth),
+            ];
+            for tree in &trees {
+                assert_same_values(tree, &tree.simplify());
+            }
+        }
+    }
+
+    #[test]
+    fn removes_double_inverses() {
+        let sine = Operation::Sine(Operation::VarX.into());
+        assert_simplifies(
+            &Operation::Inverse(Operation::Inverse(sine.clone().into()).into()),
+            &sine,
+        );
+    }
+
+    #[test]
+    fn drops_finite_factors_of_zero() {
+        let product = Operation::Product(
+            Operation::Sine(Operation::VarY.into()).into(),
+            Operation::Constant(0.0).into(),
+        );
+        assert_simplifies(&product, &Operation::Constant(0.0));
+
+        // x % y is NaN where y is 0, so it must not be dropped
+        let unbounded = Operation::Product(
+            Operation::Constant(0.0).into(),
+            Operation::Mod(Operation::VarX.into(), Operation::VarY.into()).into(),
+        );
+        assert_eq!(unbounded.simplify(), unbounded);
+    }
+
+    #[test]
+    fn collapses_masks_with_identical_branches() {
+        let branch = Operation::Tent(Operation::VarT.into());
+        let mask = |kind: fn(_, _, _, _) -> Operation| {
+            kind(
+                Box::new(Operation::VarX),
+                Box::new(branch.clone()),
+                Box::new(branch.clone()),
+                0.3,
+            )
+        };
+        assert_simplifies(&mask(Operation::BinaryMask), &branch);
+        assert_simplifies(&mask(Operation::PerChannelMask), &branch);
+    }
+
+    #[test]
+    fn removes_transforms_of_constant_subtrees() {
+        let constant = Operation::Sine(Operation::VarT.into());
+        assert_simplifies(&Operation::Rotate(1.0, constant.clone().into()), &constant);
+        assert_simplifies(&Operation::Polar(constant.clone().into()), &constant);
+        assert_simplifies(
+            &Operation::Scale(1.0, 1.0, Operation::VarX.into()),
+            &Operation::VarX,
+        );
+        assert_simplifies(
+            &Operation::Translate(0.0, 0.0, Operation::VarY.into()),
+            &Operation::VarY,
+        );
+    }
+}
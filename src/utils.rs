@@ -1,3 +1,4 @@
+use crate::renderer::ColorMapping;
 use crate::vec3::Vec3;
 use image::{ImageBuffer, ImageError, Rgb, RgbImage};
 use thiserror::Error;
@@ -7,6 +8,12 @@ pub enum ImageWriteError {
     #[error("Image error: {0}")]
     ImageError(#[from] ImageError),
 
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("PNG encoding error: {0}")]
+    PngError(#[from] png::EncodingError),
+
     #[error("Failed to write image")]
     GenericError,
 }
@@ -16,19 +23,19 @@ pub fn write_image(
     x_res: usize,
     y_res: usize,
     values: &[Vec3],
+    color: ColorMapping,
 ) -> Result<(), ImageWriteError> {
+    to_image(x_res, y_res, values, color).save(filename)?;
+    Ok(())
+}
+
+pub fn to_image(x_res: usize, y_res: usize, values: &[Vec3], color: ColorMapping) -> RgbImage {
     let mut img: RgbImage = ImageBuffer::new(x_res as u32, y_res as u32);
 
     for (x, y, pixel) in img.enumerate_pixels_mut() {
         let index = x as usize + y as usize * x_res;
-        let color = values[index];
-        *pixel = Rgb([
-            (color.x().clamp(0.0, 1.0) * 255.0) as u8,
-            (color.y().clamp(0.0, 1.0) * 255.0) as u8,
-            (color.z().clamp(0.0, 1.0) * 255.0) as u8,
-        ]);
+        *pixel = Rgb(color.map(values[index]));
     }
 
-    img.save(filename)?;
-    Ok(())
+    img
 }
@@ -1,6 +1,7 @@
 use std::ops::{Add, Mul, Sub};
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vec3 {
     e: [f64; 3],
 }
@@ -33,6 +34,16 @@ impl Vec3 {
             e: [f(self.e[0]), f(self.e[1]), f(self.e[2])],
         }
     }
+
+    pub fn zip_map(&self, other: Vec3, f: impl Fn(f64, f64) -> f64) -> Vec3 {
+        Vec3 {
+            e: [
+                f(self.e[0], other.e[0]),
+                f(self.e[1], other.e[1]),
+                f(self.e[2], other.e[2]),
+            ],
+        }
+    }
 }
 
 impl Add for Vec3 {